@@ -1,26 +1,1309 @@
-use std::collections::VecDeque;
-use std::default;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::time::Duration;
 
-use bevy::ecs::system::Spawn;
 use bevy::reflect::TypeUuid;
-use bevy::window::{WindowId, WindowResizeConstraints};
 use bevy::{math::*, prelude::*};
-use rand::{thread_rng, Rng};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 use crate::*;
 
-#[derive(serde::Deserialize, TypeUuid, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, TypeUuid, Debug, Clone)]
 #[uuid = "413be529-bfeb-41b3-9db0-4b8b380a2c46"]
 pub struct Level {
-    pub path: Vec<[f32; 2]>,
+    /// Left out by maze levels, where enemies find their own way.
+    /// Older level files have a single `path` instead.
+    #[serde(
+        default,
+        alias = "path",
+        deserialize_with = "one_or_more_paths",
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "tile_format::paths"
+    )]
+    pub paths: Vec<Vec<[f32; 2]>>,
+    #[serde(serialize_with = "tile_format::decor")]
     pub decor: Vec<(String, [f32; 2], bool)>,
-    pub center_pos: [f32; 2],
+    /// Camera focus in grid units, the middle of the map when left out
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "tile_format::optional_tile"
+    )]
+    pub center_pos: Option<[f32; 2]>,
+    /// Map size in tiles, centered on the origin
+    #[serde(
+        default = "default_map_size",
+        skip_serializing_if = "is_default_map_size"
+    )]
+    pub width: i32,
+    #[serde(
+        default = "default_map_size",
+        skip_serializing_if = "is_default_map_size"
+    )]
+    pub height: i32,
+    /// Drives all randomness in the level, see `SeedOverride` and `GameRng`
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub seed: u64,
+    /// Rows of terrain symbols from the top of the map down, one per tile.
+    /// Every tile is plain dirt when left out.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub terrain: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub terrain_types: BTreeMap<char, TerrainType>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub waves: Vec<Vec<WaveGroup>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endless: Option<Endless>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forks: Vec<Fork>,
+    /// Lives the player starts with
+    #[serde(default = "default_lives", skip_serializing_if = "is_default_lives")]
+    pub lives: u32,
+    /// Money the player starts with
+    #[serde(default = "default_money", skip_serializing_if = "is_default_money")]
+    pub money: u32,
+    /// Plays the level as a maze instead of on `paths`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maze: Option<Maze>,
+}
+
+/// A run of enemies of one kind inside a wave. Times are in seconds.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct WaveGroup {
+    pub kind: EnemyKind,
+    pub count: usize,
+    pub interval: f32,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub delay: f32,
+    /// Index into `Level::paths`
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub path: usize,
+}
+
+/// How the last wave keeps growing once the level runs out of waves
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy)]
+pub struct Endless {
+    pub count_growth: f32,
+    pub interval_scale: f32,
+}
+
+/// Maze mode: there are no fixed paths, enemies find their own way from `spawn` to `exit`
+/// around the turrets and turrets can't close that way off. Positions are in grid units.
+#[derive(serde::Deserialize, serde::Serialize, Resource, Debug, Clone, Copy)]
+pub struct Maze {
+    #[serde(serialize_with = "tile_format::tile")]
+    pub spawn: [f32; 2],
+    #[serde(serialize_with = "tile_format::tile")]
+    pub exit: [f32; 2],
+}
+
+impl Maze {
+    pub fn spawn_cell(&self) -> IVec2 {
+        GridPos::from_tiles(self.spawn).0
+    }
+
+    pub fn exit_cell(&self) -> IVec2 {
+        GridPos::from_tiles(self.exit).0
+    }
+}
+
+/// Splits `paths[path]` after tile `at` into extra branches that merge back into the same path
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct Fork {
+    pub path: usize,
+    pub at: usize,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub rule: ForkRule,
+    /// Weight of staying on the main path
+    #[serde(default = "default_weight", skip_serializing_if = "is_default_weight")]
+    pub weight: f32,
+    pub branches: Vec<Branch>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct Branch {
+    #[serde(serialize_with = "tile_format::tiles")]
+    pub tiles: Vec<[f32; 2]>,
+    /// Index of the main path tile the branch leads back into
+    pub rejoin: usize,
+    #[serde(default = "default_weight", skip_serializing_if = "is_default_weight")]
+    pub weight: f32,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct TerrainType {
+    #[serde(skip_serializing_if = "is_default_texture")]
+    pub texture: String,
+    /// Tint as `[r, g, b]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "is_buildable")]
+    pub buildable: bool,
+    /// Extra range in tiles for guns built on this terrain
+    #[serde(skip_serializing_if = "is_default")]
+    pub range_bonus: f32,
+}
+
+impl Default for TerrainType {
+    fn default() -> Self {
+        Self {
+            texture: "resources/dirt.png".to_string(),
+            color: None,
+            buildable: true,
+            range_bonus: 0.,
+        }
+    }
+}
+
+impl TerrainType {
+    pub fn color(&self) -> Color {
+        self.color
+            .map(|[r, g, b]| Color::rgb(r, g, b))
+            .unwrap_or(Color::WHITE)
+    }
+}
+
+fn default_map_size() -> i32 {
+    2 * MAP_SIZE
+}
+
+fn default_lives() -> u32 {
+    STARTING_LIVES
+}
+
+fn default_money() -> u32 {
+    STARTING_MONEY
+}
+
+fn default_weight() -> f32 {
+    1.
+}
+
+fn one_or_more_paths<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Vec<[f32; 2]>>, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Paths {
+        Many(Vec<Vec<[f32; 2]>>),
+        One(Vec<[f32; 2]>),
+    }
+
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        Paths::Many(paths) => paths,
+        Paths::One(path) => vec![path],
+    })
+}
+
+// Fields at their default are left out when the editor saves a level
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+fn is_default_map_size(size: &i32) -> bool {
+    *size == default_map_size()
+}
+
+fn is_default_lives(lives: &u32) -> bool {
+    *lives == default_lives()
+}
+
+fn is_default_money(money: &u32) -> bool {
+    *money == default_money()
+}
+
+fn is_default_weight(weight: &f32) -> bool {
+    *weight == default_weight()
+}
+
+fn is_default_texture(texture: &String) -> bool {
+    *texture == TerrainType::default().texture
+}
+
+fn is_buildable(buildable: &bool) -> bool {
+    *buildable
+}
+
+/// Writes tile positions as whole numbers like the hand written level files do,
+/// `-13` rather than `-13.0`
+mod tile_format {
+    use serde::{ser::SerializeTuple, Serialize, Serializer};
+
+    struct Tile([f32; 2]);
+
+    impl Serialize for Tile {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut tuple = serializer.serialize_tuple(2)?;
+            for coord in self.0 {
+                if coord.fract() == 0. {
+                    tuple.serialize_element(&(coord as i32))?;
+                } else {
+                    tuple.serialize_element(&coord)?;
+                }
+            }
+            tuple.end()
+        }
+    }
+
+    pub fn tile<S: Serializer>(tile: &[f32; 2], serializer: S) -> Result<S::Ok, S::Error> {
+        Tile(*tile).serialize(serializer)
+    }
+
+    pub fn optional_tile<S: Serializer>(
+        tile: &Option<[f32; 2]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        tile.map(Tile).serialize(serializer)
+    }
+
+    pub fn tiles<S: Serializer>(tiles: &[[f32; 2]], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(tiles.iter().copied().map(Tile))
+    }
+
+    pub fn paths<S: Serializer>(paths: &[Vec<[f32; 2]>], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            paths
+                .iter()
+                .map(|path| path.iter().copied().map(Tile).collect::<Vec<_>>()),
+        )
+    }
+
+    pub fn decor<S: Serializer>(
+        decor: &[(String, [f32; 2], bool)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            decor
+                .iter()
+                .map(|(image, pos, blocking)| (image, Tile(*pos), blocking)),
+        )
+    }
+}
+
+/// How an enemy picks a way at a fork
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ForkRule {
+    /// Weighted random choice
+    #[default]
+    Random,
+    /// Every way in turn, main path first
+    Alternate,
+    /// Always the way with the least distance left to the end
+    Shortest,
+}
+
+impl Level {
+    /// Checks the level for mistakes that would otherwise hang or crash `spawn_level`.
+    /// Every problem found is reported, not just the first one.
+    pub fn validate(&self, enemies: &EnemyTable) -> Result<(), Vec<LevelError>> {
+        let mut errors = vec![];
+        let mut path_tiles: HashMap<IVec2, usize> = HashMap::new();
+
+        let bounds = self.bounds();
+
+        if self.width <= 0 || self.height <= 0 {
+            errors.push(LevelError::InvalidSize {
+                width: self.width,
+                height: self.height,
+            });
+        }
+
+        if self.paths.is_empty() && self.maze.is_none() {
+            errors.push(LevelError::NoPaths);
+        }
+
+        // Endless waves grow out of the last written one
+        if self.waves.is_empty() {
+            errors.push(LevelError::NoWaves);
+        }
+
+        if let Some(maze) = self.maze {
+            if !self.paths.is_empty() || !self.forks.is_empty() {
+                errors.push(LevelError::MazeWithPaths);
+            }
+            let ends = [maze.spawn_cell(), maze.exit_cell()];
+            for pos in ends {
+                if !bounds.contains(pos) {
+                    errors.push(LevelError::MazeOutOfBounds { pos });
+                }
+                *path_tiles.entry(pos).or_default() += 1;
+            }
+            if ends.iter().all(|pos| bounds.contains(*pos)) && self.maze_route().is_none() {
+                errors.push(LevelError::MazeBlocked);
+            }
+        }
+
+        for (path_idx, path) in self.paths.iter().enumerate() {
+            let path: Vec<IVec2> = path.iter().map(|pos| GridPos::from_tiles(*pos).0).collect();
+            validate_path(path_idx, &path, &bounds, &mut errors);
+            for pos in path {
+                *path_tiles.entry(pos).or_default() += 1;
+            }
+        }
+
+        for (fork_idx, fork) in self.forks.iter().enumerate() {
+            for pos in validate_fork(fork_idx, fork, &self.paths, &bounds, &mut errors) {
+                *path_tiles.entry(pos).or_default() += 1;
+            }
+        }
+
+        for (wave, groups) in self.waves.iter().enumerate() {
+            for (group, wave_group) in groups.iter().enumerate() {
+                if wave_group.interval < 0. || wave_group.delay < 0. {
+                    errors.push(LevelError::NegativeWaveTime { wave, group });
+                }
+                if wave_group.path >= self.path_count() {
+                    errors.push(LevelError::UnknownPath {
+                        wave,
+                        group,
+                        path: wave_group.path,
+                    });
+                }
+                if !enemies.0.contains_key(&wave_group.kind) {
+                    errors.push(LevelError::UnknownEnemy {
+                        wave,
+                        group,
+                        kind: wave_group.kind.clone(),
+                    });
+                }
+            }
+        }
+
+        for (kind, enemy_type) in &enemies.0 {
+            if let Some(split) = &enemy_type.split {
+                if !enemies.0.contains_key(&split.kind) {
+                    errors.push(LevelError::UnknownSplit {
+                        kind: kind.clone(),
+                        split: split.kind.clone(),
+                    });
+                }
+            }
+            let frames = enemy_type.columns * enemy_type.rows;
+            if frames == 0 {
+                errors.push(LevelError::EmptySpriteSheet {
+                    kind: kind.clone(),
+                    columns: enemy_type.columns,
+                    rows: enemy_type.rows,
+                });
+            }
+            if let Some(animation) = &enemy_type.animation {
+                if frames > 0 && (animation.first > animation.last || animation.last >= frames) {
+                    errors.push(LevelError::AnimationFrames {
+                        kind: kind.clone(),
+                        first: animation.first,
+                        last: animation.last,
+                        frames,
+                    });
+                }
+                if animation.frame_time <= 0. {
+                    errors.push(LevelError::AnimationFrameTime { kind: kind.clone() });
+                }
+            }
+        }
+
+        if let Some(endless) = self.endless {
+            if endless.interval_scale <= 0. {
+                errors.push(LevelError::InvalidEndless);
+            }
+        }
+
+        if !self.terrain.is_empty() {
+            self.validate_terrain(&mut errors);
+        }
+
+        for (index, (image, pos, _)) in self.decor.iter().enumerate() {
+            let pos = GridPos::from_tiles(*pos).0;
+            if !bounds.contains(pos) {
+                errors.push(LevelError::DecorOutOfBounds { index, pos });
+            }
+            if path_tiles.contains_key(&pos) {
+                errors.push(LevelError::DecorOnPath {
+                    index,
+                    image: image.clone(),
+                    pos,
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn validate_terrain(&self, errors: &mut Vec<LevelError>) {
+        if self.terrain.len() != self.height.max(0) as usize {
+            errors.push(LevelError::TerrainHeight {
+                rows: self.terrain.len(),
+                height: self.height,
+            });
+        }
+
+        for (row, line) in self.terrain.iter().enumerate() {
+            if line.chars().count() != self.width.max(0) as usize {
+                errors.push(LevelError::TerrainWidth {
+                    row,
+                    len: line.chars().count(),
+                    width: self.width,
+                });
+            }
+            for (column, symbol) in line.chars().enumerate() {
+                if !self.terrain_types.contains_key(&symbol) {
+                    errors.push(LevelError::UnknownTerrain {
+                        row,
+                        column,
+                        symbol,
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn terrain(&self) -> Terrain {
+        let bounds = self.bounds();
+        let mut terrain = Terrain {
+            bounds,
+            ..default()
+        };
+        if self.terrain.is_empty() {
+            return terrain;
+        }
+
+        let symbols: Vec<char> = self.terrain_types.keys().copied().collect();
+        terrain.types.extend(
+            symbols
+                .iter()
+                .map(|symbol| self.terrain_types[symbol].clone()),
+        );
+
+        let size = bounds.max - bounds.min;
+        terrain.cells = vec![0; (size.x * size.y) as usize];
+        for (row, line) in self.terrain.iter().enumerate() {
+            for (column, symbol) in line.chars().enumerate() {
+                let cell = IVec2::new(bounds.min.x + column as i32, bounds.max.y - 1 - row as i32);
+                if let (Some(idx), Ok(type_idx)) =
+                    (terrain.index(cell), symbols.binary_search(&symbol))
+                {
+                    // Index 0 is the default dirt
+                    terrain.cells[idx] = type_idx + 1;
+                }
+            }
+        }
+
+        terrain
+    }
+
+    pub fn bounds(&self) -> MapBounds {
+        let size = IVec2::new(self.width, self.height);
+        MapBounds {
+            min: -size / 2,
+            max: size - size / 2,
+        }
+    }
+
+    /// Paths waves can spawn on, a maze only has the one from its spawn
+    pub fn path_count(&self) -> usize {
+        match self.maze {
+            Some(_) => 1,
+            None => self.paths.len(),
+        }
+    }
+
+    /// Shortest way through the maze when no turrets are built,
+    /// `None` without a maze or when blocking decor cuts the exit off
+    pub fn maze_route(&self) -> Option<Vec<IVec2>> {
+        let maze = self.maze?;
+        let bounds = self.bounds();
+        let blocked: HashSet<IVec2> = self
+            .decor
+            .iter()
+            .filter(|(_, _, blocks)| *blocks)
+            .map(|(_, pos, _)| GridPos::from_tiles(*pos).0)
+            .collect();
+
+        find_path(maze.spawn_cell(), maze.exit_cell(), |cell| {
+            bounds.contains(cell) && !blocked.contains(&cell)
+        })
+    }
+
+    /// Grid position the camera looks at when the level starts
+    pub fn center(&self) -> Vec2 {
+        self.center_pos
+            .map(Vec2::from_array)
+            .unwrap_or_else(|| self.bounds().center())
+    }
+}
+
+fn validate_path(
+    path_idx: usize,
+    path: &[IVec2],
+    bounds: &MapBounds,
+    errors: &mut Vec<LevelError>,
+) {
+    match path.len() {
+        0 => errors.push(LevelError::EmptyPath { path: path_idx }),
+        1 => errors.push(LevelError::PathTooShort { path: path_idx }),
+        _ => {}
+    }
+
+    let mut visited: HashMap<IVec2, usize> = HashMap::new();
+    for (index, pos) in path.iter().enumerate() {
+        if !bounds.contains(*pos) {
+            errors.push(LevelError::PathOutOfBounds {
+                path: path_idx,
+                index,
+                pos: *pos,
+            });
+        }
+
+        if index > 0 {
+            let prev = path[index - 1];
+            if prev == *pos {
+                errors.push(LevelError::DuplicateTile {
+                    path: path_idx,
+                    index,
+                    first: index - 1,
+                    pos: *pos,
+                });
+                continue;
+            }
+            if (*pos - prev).abs().to_array().iter().sum::<i32>() != 1 {
+                errors.push(LevelError::Gap {
+                    path: path_idx,
+                    index,
+                    from: prev,
+                    to: *pos,
+                });
+            }
+        }
+
+        match visited.get(pos) {
+            // The path may cross itself, but only straight across
+            Some(&first) if !is_crossing(path, first, index) => {
+                errors.push(LevelError::DuplicateTile {
+                    path: path_idx,
+                    index,
+                    first,
+                    pos: *pos,
+                });
+            }
+            Some(_) => {}
+            None => {
+                visited.insert(*pos, index);
+            }
+        }
+    }
+}
+
+// Returns the branch tiles so decor can be checked against them too
+fn validate_fork(
+    fork_idx: usize,
+    fork: &Fork,
+    paths: &[Vec<[f32; 2]>],
+    bounds: &MapBounds,
+    errors: &mut Vec<LevelError>,
+) -> Vec<IVec2> {
+    let Some(path) = paths.get(fork.path) else {
+        errors.push(LevelError::ForkUnknownPath {
+            fork: fork_idx,
+            path: fork.path,
+        });
+        return vec![];
+    };
+    let path: Vec<IVec2> = path.iter().map(|pos| GridPos::from_tiles(*pos).0).collect();
+
+    if fork.at + 1 >= path.len() {
+        errors.push(LevelError::ForkOutOfRange {
+            fork: fork_idx,
+            at: fork.at,
+        });
+        return vec![];
+    }
+
+    if fork.weight < 0. || fork.branches.iter().any(|branch| branch.weight < 0.) {
+        errors.push(LevelError::NegativeWeight { fork: fork_idx });
+    }
+
+    let mut branch_tiles = vec![];
+    for (branch_idx, branch) in fork.branches.iter().enumerate() {
+        let tiles: Vec<IVec2> = branch
+            .tiles
+            .iter()
+            .map(|pos| GridPos::from_tiles(*pos).0)
+            .collect();
+        for (index, pos) in tiles.iter().enumerate() {
+            if !bounds.contains(*pos) {
+                errors.push(LevelError::BranchOutOfBounds {
+                    fork: fork_idx,
+                    branch: branch_idx,
+                    index,
+                    pos: *pos,
+                });
+            }
+        }
+
+        if branch.rejoin <= fork.at || branch.rejoin >= path.len() {
+            errors.push(LevelError::BranchRejoin {
+                fork: fork_idx,
+                branch: branch_idx,
+                rejoin: branch.rejoin,
+            });
+        } else {
+            // Both ends of the branch have to connect to the main path too
+            let route: Vec<IVec2> = [path[fork.at]]
+                .into_iter()
+                .chain(tiles.iter().copied())
+                .chain([path[branch.rejoin]])
+                .collect();
+            for (index, step) in route.windows(2).enumerate() {
+                if (step[1] - step[0]).abs().to_array().iter().sum::<i32>() != 1 {
+                    errors.push(LevelError::BranchGap {
+                        fork: fork_idx,
+                        branch: branch_idx,
+                        index,
+                        from: step[0],
+                        to: step[1],
+                    });
+                }
+            }
+        }
+
+        branch_tiles.extend(tiles);
+    }
+
+    branch_tiles
+}
+
+// Two visits of the same tile form a crossing when both pass straight through it at a right angle
+fn is_crossing(path: &[IVec2], a: usize, b: usize) -> bool {
+    let through = |i: usize| {
+        if i == 0 || i + 1 >= path.len() {
+            return None;
+        }
+        let (dir_in, dir_out) = (path[i] - path[i - 1], path[i + 1] - path[i]);
+        (dir_in == dir_out).then_some(dir_in)
+    };
+
+    match (through(a), through(b)) {
+        (Some(dir_a), Some(dir_b)) => dir_a.dot(dir_b) == 0,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelError {
+    LoadFailed(String),
+    NoPaths,
+    NoWaves,
+    EmptyPath {
+        path: usize,
+    },
+    PathTooShort {
+        path: usize,
+    },
+    Gap {
+        path: usize,
+        index: usize,
+        from: IVec2,
+        to: IVec2,
+    },
+    DuplicateTile {
+        path: usize,
+        index: usize,
+        first: usize,
+        pos: IVec2,
+    },
+    PathOutOfBounds {
+        path: usize,
+        index: usize,
+        pos: IVec2,
+    },
+    DecorOutOfBounds {
+        index: usize,
+        pos: IVec2,
+    },
+    DecorOnPath {
+        index: usize,
+        image: String,
+        pos: IVec2,
+    },
+    NegativeWaveTime {
+        wave: usize,
+        group: usize,
+    },
+    UnknownPath {
+        wave: usize,
+        group: usize,
+        path: usize,
+    },
+    UnknownEnemy {
+        wave: usize,
+        group: usize,
+        kind: EnemyKind,
+    },
+    UnknownSplit {
+        kind: EnemyKind,
+        split: EnemyKind,
+    },
+    EmptySpriteSheet {
+        kind: EnemyKind,
+        columns: usize,
+        rows: usize,
+    },
+    AnimationFrames {
+        kind: EnemyKind,
+        first: usize,
+        last: usize,
+        frames: usize,
+    },
+    AnimationFrameTime {
+        kind: EnemyKind,
+    },
+    InvalidEndless,
+    InvalidSize {
+        width: i32,
+        height: i32,
+    },
+    TerrainHeight {
+        rows: usize,
+        height: i32,
+    },
+    TerrainWidth {
+        row: usize,
+        len: usize,
+        width: i32,
+    },
+    UnknownTerrain {
+        row: usize,
+        column: usize,
+        symbol: char,
+    },
+    ForkUnknownPath {
+        fork: usize,
+        path: usize,
+    },
+    ForkOutOfRange {
+        fork: usize,
+        at: usize,
+    },
+    NegativeWeight {
+        fork: usize,
+    },
+    BranchRejoin {
+        fork: usize,
+        branch: usize,
+        rejoin: usize,
+    },
+    BranchGap {
+        fork: usize,
+        branch: usize,
+        index: usize,
+        from: IVec2,
+        to: IVec2,
+    },
+    BranchOutOfBounds {
+        fork: usize,
+        branch: usize,
+        index: usize,
+        pos: IVec2,
+    },
+    MazeWithPaths,
+    MazeOutOfBounds {
+        pos: IVec2,
+    },
+    MazeBlocked,
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LoadFailed(file) => write!(f, "could not load or parse {file}"),
+            Self::NoPaths => write!(f, "level has no paths"),
+            Self::NoWaves => write!(f, "level has no waves"),
+            Self::EmptyPath { path } => write!(f, "paths[{path}] is empty"),
+            Self::PathTooShort { path } => {
+                write!(f, "paths[{path}] needs at least a start and an end tile")
+            }
+            Self::Gap {
+                path,
+                index,
+                from,
+                to,
+            } => write!(
+                f,
+                "paths[{path}][{index}] [{}, {}] is not next to the previous tile [{}, {}]",
+                to.x, to.y, from.x, from.y
+            ),
+            Self::DuplicateTile {
+                path,
+                index,
+                first,
+                pos,
+            } => write!(
+                f,
+                "paths[{path}][{index}] [{}, {}] repeats paths[{path}][{first}]",
+                pos.x, pos.y
+            ),
+            Self::PathOutOfBounds { path, index, pos } => write!(
+                f,
+                "paths[{path}][{index}] [{}, {}] is outside the map",
+                pos.x, pos.y
+            ),
+            Self::DecorOutOfBounds { index, pos } => write!(
+                f,
+                "decor[{index}] [{}, {}] is outside the map",
+                pos.x, pos.y
+            ),
+            Self::DecorOnPath { index, image, pos } => write!(
+                f,
+                "decor[{index}] {image} at [{}, {}] sits on a path",
+                pos.x, pos.y
+            ),
+            Self::NegativeWaveTime { wave, group } => {
+                write!(f, "waves[{wave}][{group}] has a negative interval or delay")
+            }
+            Self::UnknownPath { wave, group, path } => {
+                write!(f, "waves[{wave}][{group}] spawns on missing paths[{path}]")
+            }
+            Self::UnknownEnemy { wave, group, kind } => {
+                write!(f, "waves[{wave}][{group}] spawns unknown enemy {kind}")
+            }
+            Self::UnknownSplit { kind, split } => {
+                write!(f, "enemy {kind} splits into unknown enemy {split}")
+            }
+            Self::EmptySpriteSheet {
+                kind,
+                columns,
+                rows,
+            } => write!(
+                f,
+                "enemy {kind} has a {columns}x{rows} sprite sheet, it needs at least one frame"
+            ),
+            Self::AnimationFrames {
+                kind,
+                first,
+                last,
+                frames,
+            } => write!(
+                f,
+                "enemy {kind} animates frames {first} to {last} but its sprite sheet has {frames}"
+            ),
+            Self::AnimationFrameTime { kind } => {
+                write!(f, "enemy {kind} animation frame_time must be above 0")
+            }
+            Self::InvalidEndless => write!(f, "endless interval_scale must be above 0"),
+            Self::InvalidSize { width, height } => {
+                write!(f, "map size {width}x{height} must be at least 1x1")
+            }
+            Self::TerrainHeight { rows, height } => {
+                write!(f, "terrain has {rows} rows but the map is {height} tall")
+            }
+            Self::TerrainWidth { row, len, width } => write!(
+                f,
+                "terrain[{row}] has {len} tiles but the map is {width} wide"
+            ),
+            Self::UnknownTerrain {
+                row,
+                column,
+                symbol,
+            } => write!(
+                f,
+                "terrain[{row}] column {column} uses '{symbol}', which is not in terrain_types"
+            ),
+            Self::ForkUnknownPath { fork, path } => {
+                write!(f, "forks[{fork}] splits missing paths[{path}]")
+            }
+            Self::ForkOutOfRange { fork, at } => {
+                write!(f, "forks[{fork}] splits at {at}, which is not before the end")
+            }
+            Self::NegativeWeight { fork } => write!(f, "forks[{fork}] has a negative weight"),
+            Self::BranchRejoin {
+                fork,
+                branch,
+                rejoin,
+            } => write!(
+                f,
+                "forks[{fork}].branches[{branch}] rejoins at {rejoin}, which is not after the fork"
+            ),
+            Self::BranchGap {
+                fork,
+                branch,
+                index,
+                from,
+                to,
+            } => write!(
+                f,
+                "forks[{fork}].branches[{branch}] step {index} from [{}, {}] to [{}, {}] is not to a neighbouring tile",
+                from.x, from.y, to.x, to.y
+            ),
+            Self::BranchOutOfBounds {
+                fork,
+                branch,
+                index,
+                pos,
+            } => write!(
+                f,
+                "forks[{fork}].branches[{branch}].tiles[{index}] [{}, {}] is outside the map",
+                pos.x, pos.y
+            ),
+            Self::MazeWithPaths => write!(f, "a maze can't have paths or forks"),
+            Self::MazeOutOfBounds { pos } => write!(
+                f,
+                "maze spawn or exit [{}, {}] is outside the map",
+                pos.x, pos.y
+            ),
+            Self::MazeBlocked => write!(f, "decor blocks every way from the maze spawn to its exit"),
+        }
+    }
+}
+
+/// Grid cells from `min` up to but not including `max`
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapBounds {
+    pub min: IVec2,
+    pub max: IVec2,
+}
+
+impl Default for MapBounds {
+    fn default() -> Self {
+        Self {
+            min: IVec2::splat(-MAP_SIZE),
+            max: IVec2::splat(MAP_SIZE),
+        }
+    }
 }
 
+impl MapBounds {
+    pub fn contains(&self, cell: IVec2) -> bool {
+        cell.cmpge(self.min).all() && cell.cmplt(self.max).all()
+    }
+
+    pub fn center(&self) -> Vec2 {
+        (self.min + self.max).as_vec2() / 2.
+    }
+
+    /// Lower left and upper right corners in world units
+    pub fn world_rect(&self) -> (Vec2, Vec2) {
+        (GridPos(self.min).corner(), GridPos(self.max).corner())
+    }
+}
+
+/// Terrain of every tile on the map, see `Level::terrain`
 #[derive(Resource, Debug)]
+pub struct Terrain {
+    pub bounds: MapBounds,
+    /// Index 0 is the default terrain
+    pub types: Vec<TerrainType>,
+    pub cells: Vec<usize>,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Self {
+            bounds: default(),
+            types: vec![TerrainType::default()],
+            cells: vec![],
+        }
+    }
+}
+
+impl Terrain {
+    fn index(&self, cell: IVec2) -> Option<usize> {
+        if !self.bounds.contains(cell) {
+            return None;
+        }
+        let width = self.bounds.max.x - self.bounds.min.x;
+        let offset = cell - self.bounds.min;
+        Some((offset.y * width + offset.x) as usize)
+    }
+
+    /// Terrain at `cell`, `None` outside the map
+    pub fn get(&self, cell: IVec2) -> Option<&TerrainType> {
+        let idx = self.index(cell)?;
+        Some(&self.types[self.cells.get(idx).copied().unwrap_or(0)])
+    }
+
+    pub fn is_buildable(&self, cells: impl IntoIterator<Item = IVec2>) -> bool {
+        cells
+            .into_iter()
+            .all(|cell| self.get(cell).is_some_and(|terrain| terrain.buildable))
+    }
+
+    /// Best range bonus under a footprint, in tiles
+    pub fn range_bonus(&self, cells: impl IntoIterator<Item = IVec2>) -> f32 {
+        cells
+            .into_iter()
+            .filter_map(|cell| self.get(cell))
+            .map(|terrain| terrain.range_bonus)
+            .fold(0., f32::max)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Occupant {
+    Path,
+    /// Decor that blocks building
+    Decor,
+    /// Terrain that can't be built on
+    Terrain,
+    Turret(Entity),
+}
+
+/// What sits on each grid cell of the map, so placing, selling and hovering
+/// only look at the cells under the cursor. Free cells have no entry.
+#[derive(Resource, Debug, Default)]
+pub struct Occupancy {
+    pub bounds: MapBounds,
+    cells: HashMap<IVec2, Occupant>,
+}
+
+impl Occupancy {
+    /// Replaces the level's cells, keeping the turrets.
+    /// A turret under a new path, decor or terrain loses those cells.
+    pub fn set_level(&mut self, level: &Level, terrain: &Terrain) {
+        self.bounds = level.bounds();
+        self.cells
+            .retain(|_, occupant| matches!(occupant, Occupant::Turret(_)));
+
+        for x in self.bounds.min.x..self.bounds.max.x {
+            for y in self.bounds.min.y..self.bounds.max.y {
+                let cell = IVec2::new(x, y);
+                if !terrain.is_buildable([cell]) {
+                    self.cells.insert(cell, Occupant::Terrain);
+                }
+            }
+        }
+        for (_, pos, blocks) in &level.decor {
+            if *blocks {
+                self.cells
+                    .insert(GridPos::from_tiles(*pos).0, Occupant::Decor);
+            }
+        }
+        let branch_tiles = level
+            .forks
+            .iter()
+            .flat_map(|fork| fork.branches.iter().flat_map(|branch| branch.tiles.iter()));
+        for pos in level.paths.iter().flatten().chain(branch_tiles) {
+            self.cells
+                .insert(GridPos::from_tiles(*pos).0, Occupant::Path);
+        }
+        if let Some(maze) = level.maze {
+            self.add_path([maze.spawn_cell(), maze.exit_cell()]);
+        }
+    }
+
+    pub fn get(&self, cell: IVec2) -> Option<Occupant> {
+        self.cells.get(&cell).copied()
+    }
+
+    /// Whether every cell is inside the map with nothing on it
+    pub fn is_free(&self, cells: impl IntoIterator<Item = IVec2>) -> bool {
+        cells
+            .into_iter()
+            .all(|cell| self.bounds.contains(cell) && !self.cells.contains_key(&cell))
+    }
+
+    /// Whether enemies can walk over the cell in a maze
+    pub fn is_walkable(&self, cell: IVec2) -> bool {
+        self.bounds.contains(cell)
+            && !matches!(self.get(cell), Some(Occupant::Decor | Occupant::Turret(_)))
+    }
+
+    /// Marks the cells as path, taking them from any turret on them
+    pub fn add_path(&mut self, cells: impl IntoIterator<Item = IVec2>) {
+        for cell in cells {
+            self.cells.insert(cell, Occupant::Path);
+        }
+    }
+
+    pub fn turret_at(&self, cell: IVec2) -> Option<Entity> {
+        match self.get(cell)? {
+            Occupant::Turret(entity) => Some(entity),
+            _ => None,
+        }
+    }
+
+    /// Whether the turret still has all of its cells inside the map
+    pub fn holds(&self, entity: Entity, cells: impl IntoIterator<Item = IVec2>) -> bool {
+        cells.into_iter().all(|cell| {
+            self.bounds.contains(cell) && self.get(cell) == Some(Occupant::Turret(entity))
+        })
+    }
+
+    pub fn add_turret(&mut self, entity: Entity, cells: impl IntoIterator<Item = IVec2>) {
+        for cell in cells {
+            self.cells.insert(cell, Occupant::Turret(entity));
+        }
+    }
+
+    /// Frees the cells the turret still holds among `cells`
+    pub fn remove_turret(&mut self, entity: Entity, cells: impl IntoIterator<Item = IVec2>) {
+        for cell in cells {
+            if self.get(cell) == Some(Occupant::Turret(entity)) {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+}
+
+#[derive(Resource, Debug, Default)]
 pub struct LevelHandle(pub Handle<Level>);
 
+/// Game time, moved on by exactly `TIME_STEP` every fixed step.
+/// Gameplay timers go by this rather than `Time`, so they don't depend on the frame rate
+/// and stand still whenever the fixed step doesn't run.
+#[derive(Resource, Debug, Default)]
+pub struct SimTime {
+    pub elapsed: Duration,
+}
+
+impl SimTime {
+    pub fn delta(&self) -> Duration {
+        Duration::from_secs_f32(TIME_STEP)
+    }
+}
+
+/// Seed given with `--seed` on the command line, used instead of `Level::seed`
+#[derive(Resource, Debug, Default, Clone)]
+pub struct SeedOverride {
+    pub seed: Option<u64>,
+    /// A `--seed` that is not a number, reported once the game starts
+    pub invalid: Option<String>,
+}
+
+impl SeedOverride {
+    pub fn from_args(args: impl Iterator<Item = String>) -> Self {
+        let mut args = args.skip_while(|arg| arg != "--seed").skip(1);
+        match args.next() {
+            Some(seed) => match seed.parse() {
+                Ok(seed) => Self {
+                    seed: Some(seed),
+                    invalid: None,
+                },
+                Err(_) => Self {
+                    seed: None,
+                    invalid: Some(seed),
+                },
+            },
+            None => Self::default(),
+        }
+    }
+
+    pub fn seed(&self, level: &Level) -> u64 {
+        self.seed.unwrap_or(level.seed)
+    }
+}
+
+/// Every random draw in a level, seeded from the level seed.
+/// Looks and gameplay draw from separate streams, so a change to the decoration
+/// never changes how a game plays out.
+#[derive(Resource, Debug)]
+pub struct GameRng {
+    pub cosmetic: StdRng,
+    pub gameplay: StdRng,
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl GameRng {
+    const COSMETIC: u64 = 0;
+    const GAMEPLAY: u64 = 1;
+
+    pub fn new(seed: u64) -> Self {
+        Self {
+            cosmetic: Self::stream(seed, Self::COSMETIC),
+            gameplay: Self::stream(seed, Self::GAMEPLAY),
+        }
+    }
+
+    /// The seed and the stream id each fill their own part of the key,
+    /// so no two (seed, stream) pairs share a sequence
+    fn stream(seed: u64, stream: u64) -> StdRng {
+        let mut key = [0; 32];
+        key[..8].copy_from_slice(&seed.to_le_bytes());
+        key[8..16].copy_from_slice(&stream.to_le_bytes());
+        StdRng::from_seed(key)
+    }
+}
+
+/// List of playable levels, in the order they are shown on the level select screen
+#[derive(serde::Deserialize, TypeUuid, Debug)]
+#[uuid = "9b4a865b-f141-4ba9-9121-f00e826f8629"]
+pub struct Campaign {
+    pub levels: Vec<CampaignLevel>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct CampaignLevel {
+    pub name: String,
+    /// Level file relative to the assets folder
+    pub file: String,
+    /// Names of the levels that have to be won first
+    #[serde(default)]
+    pub requires: Vec<String>,
+}
+
+impl CampaignLevel {
+    pub fn is_unlocked(&self, progress: &CampaignProgress) -> bool {
+        self.requires
+            .iter()
+            .all(|name| progress.completed.contains(name))
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct CampaignHandle(pub Handle<Campaign>);
+
+/// Names of the levels won so far, kept in `progress.json` next to the game
+#[derive(Resource, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct CampaignProgress {
+    pub completed: HashSet<String>,
+}
+
+impl CampaignProgress {
+    fn file() -> std::path::PathBuf {
+        bevy::asset::FileAssetIo::get_base_path().join("progress.json")
+    }
+
+    /// Progress from earlier sessions, none on the first launch
+    pub fn load() -> Self {
+        let Ok(json) = std::fs::read(Self::file()) else {
+            return default();
+        };
+        serde_json::from_slice(&json).unwrap_or_else(|err| {
+            warn!("campaign: ignoring unreadable progress.json: {err}");
+            default()
+        })
+    }
+
+    pub fn save(&self) {
+        let result = serde_json::to_vec_pretty(self)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(Self::file(), json));
+        if let Err(err) = result {
+            error!("campaign: could not save progress.json: {err}");
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct CurrentLevel(pub CampaignLevel);
+
+/// Marks entities spawned from the level file, so they can be torn down on reload
+#[derive(Component, Debug)]
+pub struct LevelEntity;
+
+#[derive(Resource, Debug, Default)]
+pub struct LevelErrors(pub Vec<LevelError>);
+
 #[derive(Component, Debug, PartialEq, PartialOrd)]
 pub struct Collider(pub ColliderType);
 
@@ -69,12 +1352,19 @@ impl Cursor {
 #[derive(Component, Debug)]
 pub struct Selected;
 
+/// Circle showing the range of the selected or hovered turret
+#[derive(Component, Debug)]
+pub struct RangePreview;
+
 #[derive(Component, Debug, Default)]
 pub struct GridCursor {
     pub can_place: bool,
     pub last_target_pos: Vec2,
     pub last_sample: f32,
     pub selection_size: Vec2,
+    /// Whether building at (`last_target_pos`, `selection_size`) closes the maze,
+    /// kept until the cursor moves or a turret is placed or sold
+    pub closes_maze: Option<((Vec2, Vec2), bool)>,
 }
 
 #[derive(Component, Debug, Clone, Copy)]
@@ -91,6 +1381,11 @@ impl Turret {
         }
     }
 
+    /// Footprint in tiles
+    pub fn size(&self) -> IVec2 {
+        (self.scale() * SPRITE_SIZE).as_ivec2()
+    }
+
     pub fn sprite(&self, asset_server: &Res<AssetServer>) -> Handle<Image> {
         asset_server.load(match self {
             Turret::Turret1x1 => "resources/turret-2.png",
@@ -104,6 +1399,20 @@ impl Turret {
             Turret::Turret2x2 => Gun::Gun2,
         }
     }
+
+    pub fn cost(&self) -> u32 {
+        match self {
+            Turret::Turret1x1 => 50,
+            Turret::Turret2x2 => 120,
+        }
+    }
+
+    /// Money paid back when the turret is sold with `tier` upgrades bought
+    pub fn refund(&self, tier: usize) -> u32 {
+        let upgrades = &self.gun().upgrades()[..tier.min(self.gun().upgrades().len())];
+        let spent = self.cost() + upgrades.iter().map(|upgrade| upgrade.cost).sum::<u32>();
+        (spent as f32 * SELL_REFUND) as u32
+    }
 }
 
 #[derive(Bundle)]
@@ -151,6 +1460,79 @@ pub enum Gun {
 #[derive(Component, Debug, Clone, Copy, Default)]
 pub struct GunState {
     pub last_shot: Duration,
+    /// Extra range in tiles from the terrain the gun is built on
+    pub range_bonus: f32,
+    pub target: TargetMode,
+    /// Number of upgrades bought, 0 is the base gun
+    pub tier: usize,
+}
+
+impl GunState {
+    /// Range of `gun` at this tier and terrain, in world units
+    pub fn range(&self, gun: &Gun) -> f32 {
+        gun.range(self.tier) + self.range_bonus * TILE_SIZE
+    }
+}
+
+/// Replaces the gun's base stats once bought
+#[derive(Debug)]
+pub struct Upgrade {
+    pub cost: u32,
+    /// Range in tiles
+    pub range: f32,
+    pub rate: f32,
+    pub projectile: ProjectileType,
+    pub sprite: &'static str,
+}
+
+const GUN1_UPGRADES: [Upgrade; 2] = [
+    Upgrade {
+        cost: 60,
+        range: 6.,
+        rate: 2.4,
+        projectile: ProjectileType::Knife,
+        sprite: "resources/gun-1.png",
+    },
+    Upgrade {
+        cost: 120,
+        range: 7.,
+        rate: 3.,
+        projectile: ProjectileType::Spoon,
+        sprite: "resources/gun-1-alt.png",
+    },
+];
+
+const GUN2_UPGRADES: [Upgrade; 1] = [Upgrade {
+    cost: 150,
+    range: 10.,
+    rate: 3.2,
+    projectile: ProjectileType::ChefsKnife,
+    sprite: "resources/gun-1.png",
+}];
+
+/// Which enemy in range a gun shoots at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TargetMode {
+    /// Furthest along its path
+    #[default]
+    First,
+    /// Least far along its path
+    Last,
+    Strongest,
+    Weakest,
+    Nearest,
+}
+
+impl TargetMode {
+    pub fn next(&self) -> Self {
+        match self {
+            Self::First => Self::Last,
+            Self::Last => Self::Strongest,
+            Self::Strongest => Self::Weakest,
+            Self::Weakest => Self::Nearest,
+            Self::Nearest => Self::First,
+        }
+    }
 }
 
 impl Gun {
@@ -161,25 +1543,93 @@ impl Gun {
         }
     }
 
-    pub fn sprite(&self, asset_server: &Res<AssetServer>) -> Handle<Image> {
-        asset_server.load(match self {
-            Gun::Gun1 => "resources/gun-2.png",
-            Gun::Gun2 => "resources/gun-1-alt.png",
-        })
+    pub fn upgrades(&self) -> &'static [Upgrade] {
+        match self {
+            Self::Gun1 => &GUN1_UPGRADES,
+            Self::Gun2 => &GUN2_UPGRADES,
+        }
+    }
+
+    /// The upgrade in effect at `tier`, `None` for the base gun
+    pub fn upgrade(&self, tier: usize) -> Option<&'static Upgrade> {
+        tier.checked_sub(1).and_then(|idx| self.upgrades().get(idx))
+    }
+
+    pub fn sprite(&self, tier: usize, asset_server: &Res<AssetServer>) -> Handle<Image> {
+        asset_server.load(self.sprite_path(tier))
+    }
+
+    pub fn sprite_path(&self, tier: usize) -> &'static str {
+        match self.upgrade(tier) {
+            Some(upgrade) => upgrade.sprite,
+            None => match self {
+                Gun::Gun1 => "resources/gun-2.png",
+                Gun::Gun2 => "resources/gun-1-alt.png",
+            },
+        }
     }
 
-    pub fn range(&self) -> f32 {
+    /// Range at `tier` in world units, without the terrain bonus
+    pub fn range(&self, tier: usize) -> f32 {
         TILE_SIZE
-            * match self {
-                Self::Gun1 => 5.0,
-                Self::Gun2 => 8.0,
+            * match self.upgrade(tier) {
+                Some(upgrade) => upgrade.range,
+                None => match self {
+                    Self::Gun1 => 5.0,
+                    Self::Gun2 => 8.0,
+                },
             }
     }
 
-    pub fn rate(&self) -> f32 {
+    pub fn rate(&self, tier: usize) -> f32 {
+        match self.upgrade(tier) {
+            Some(upgrade) => upgrade.rate,
+            None => match self {
+                Self::Gun1 => 1.8,
+                Self::Gun2 => 2.5,
+            },
+        }
+    }
+
+    pub fn projectile(&self, tier: usize) -> ProjectileType {
+        match self.upgrade(tier) {
+            Some(upgrade) => upgrade.projectile,
+            None => match self {
+                Self::Gun1 => ProjectileType::Knife,
+                Self::Gun2 => ProjectileType::ChefsKnife,
+            },
+        }
+    }
+
+    /// Health taken from each enemy a projectile hits
+    pub fn damage(&self) -> i32 {
         match self {
-            Self::Gun1 => 1.8,
-            Self::Gun2 => 2.5,
+            Self::Gun1 => 1,
+            Self::Gun2 => 2,
+        }
+    }
+
+    /// Number of enemies a projectile can hit before it breaks
+    pub fn pierce(&self) -> i32 {
+        match self {
+            Self::Gun1 => 1,
+            Self::Gun2 => 3,
+        }
+    }
+
+    /// Angle the burst is fanned out over, in radians
+    pub fn spread(&self) -> f32 {
+        match self {
+            Self::Gun1 => 30. * PI / 180.,
+            Self::Gun2 => 0.,
+        }
+    }
+
+    /// Projectiles fired per shot
+    pub fn burst(&self) -> usize {
+        match self {
+            Self::Gun1 => 3,
+            Self::Gun2 => 1,
         }
     }
 }
@@ -196,7 +1646,7 @@ impl GunBundle {
             sprite_bundle: SpriteBundle {
                 transform: Transform::from_xyz(0., 0., 10.)
                     .with_scale(Vec2::splat(1. / SPRITE_SIZE).extend(0.0)), // TODO z layer
-                texture: gun.sprite(asset_server),
+                texture: gun.sprite(0, asset_server),
                 ..default()
             },
             gun,
@@ -219,6 +1669,7 @@ impl GunBundle {
 pub struct Projectile {
     pub ty: ProjectileType,
     pub health: i32,
+    pub damage: i32,
     pub hit_enemies: Vec<Entity>,
 }
 
@@ -229,20 +1680,22 @@ pub enum ProjectileType {
     ChefsKnife,
 }
 impl ProjectileType {
-    fn initial_health(&self) -> i32 {
+    /// Animation frames in the sprite sheet
+    pub fn frames(&self) -> usize {
         match self {
             ProjectileType::Knife => 1,
-            ProjectileType::Spoon => 2,
+            ProjectileType::Spoon => 1,
             ProjectileType::ChefsKnife => 3,
         }
     }
 }
 
 impl Projectile {
-    pub fn new(ty: ProjectileType) -> Self {
+    pub fn new(ty: ProjectileType, damage: i32, pierce: i32) -> Self {
         Self {
             ty,
-            health: ty.initial_health(),
+            health: pierce,
+            damage,
             hit_enemies: vec![],
         }
     }
@@ -271,7 +1724,14 @@ impl Projectile {
             ProjectileType::ChefsKnife => "resources/chef's knife.png",
         });
 
-        TextureAtlas::from_grid(texture_handle, Vec2::new(32.0, 32.0), 3, 1, None, None)
+        TextureAtlas::from_grid(
+            texture_handle,
+            Vec2::new(32.0, 32.0),
+            self.ty.frames(),
+            1,
+            None,
+            None,
+        )
     }
 }
 #[derive(Bundle)]
@@ -342,6 +1802,11 @@ impl Tile {
         self.sprite_bundle.texture = texture;
         self
     }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.sprite_bundle.sprite.color = color;
+        self
+    }
 }
 
 // Root path
@@ -352,8 +1817,7 @@ pub struct PathTile {
 }
 
 impl PathTile {
-    pub fn new(asset_server: &Res<AssetServer>) -> Self {
-        let mut rng = thread_rng();
+    pub fn new(asset_server: &Res<AssetServer>, rng: &mut impl Rng) -> Self {
         Self {
             sprite_bundle: SpriteBundle {
                 transform: Transform::from_scale(
@@ -392,10 +1856,11 @@ pub struct EnemyBundle {
 impl EnemyBundle {
     pub fn new(
         enemy: Enemy,
+        enemy_type: &EnemyType,
         asset_server: &Res<AssetServer>,
         texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
     ) -> Self {
-        let atlas = enemy.atlas(asset_server);
+        let atlas = enemy_type.atlas(asset_server);
         let tah = texture_atlases.add(atlas); // FIXME adding the texture every time is probably wrong (branch atlas-refactoring)
 
         Self {
@@ -419,79 +1884,286 @@ impl EnemyBundle {
 pub struct Enemy {
     pub health: i32,
     pub kind: EnemyKind,
+    /// Tiles per second
+    pub speed: f32,
+    pub path: usize,
     pub idx: usize,
+    /// Node the enemy is walking to, picked when `idx` is reached
+    pub next: Option<usize>,
 }
 
 impl Enemy {
-    pub fn new(kind: EnemyKind, idx: usize) -> Self {
+    pub fn new(kind: EnemyKind, enemy_type: &EnemyType, path: usize, idx: usize) -> Self {
         Self {
-            health: kind.initial_health(),
+            health: enemy_type.health,
             kind,
+            speed: enemy_type.speed,
+            path,
             idx,
+            next: None,
         }
     }
 
     pub fn speed(&self) -> f32 {
-        TIME_STEP
-            * TILE_SIZE
-            * match self.kind {
-                EnemyKind::Potato => 2.,
-                EnemyKind::Carrot => 3.,
-                EnemyKind::Pepper => 1.,
-            }
+        TIME_STEP * TILE_SIZE * self.speed
     }
 
-    pub fn split(&self) -> Option<(i32, EnemyKind)> {
-        match self.kind {
-            EnemyKind::Potato => None,
-            EnemyKind::Carrot => Some((1, EnemyKind::Potato)),
-            EnemyKind::Pepper => Some((4, EnemyKind::Carrot)),
+    /// How far the enemy still has to walk to the end of its path
+    pub fn distance_left(&self, path: &Path, position: Vec2) -> f32 {
+        match self.next {
+            Some(next) => {
+                let node = &path.positions[next];
+                position.distance(node.position) + node.distance
+            }
+            None => path.positions[self.idx].distance,
         }
     }
+}
 
-    pub fn atlas(&self, asset_server: &Res<AssetServer>) -> TextureAtlas {
-        let texture_handle = asset_server.load(match self.kind {
-            EnemyKind::Potato => "resources/potato.png",
-            EnemyKind::Carrot => "resources/carrot.png",
-            EnemyKind::Pepper => "resources/pepper.png",
-        });
+/// Id of an entry in the enemy table, e.g. `"Potato"`
+#[derive(
+    PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, serde::Deserialize, serde::Serialize,
+)]
+#[serde(transparent)]
+pub struct EnemyKind(pub String);
+
+impl fmt::Display for EnemyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Every kind of enemy, loaded from `main.enemies.json`
+#[derive(serde::Deserialize, TypeUuid, Debug, Default)]
+#[uuid = "3eacd9ef-42f9-4c16-b05e-cdbb23391909"]
+#[serde(transparent)]
+pub struct EnemyTable(pub BTreeMap<EnemyKind, EnemyType>);
 
-        TextureAtlas::from_grid(texture_handle, Vec2::new(32.0, 32.0), 4, 1, None, None)
+impl EnemyTable {
+    pub fn get(&self, kind: &EnemyKind) -> Option<&EnemyType> {
+        self.0.get(kind)
     }
 }
 
-#[derive(PartialEq, Debug, Clone, serde::Deserialize)]
-pub enum EnemyKind {
-    Potato,
-    Carrot,
-    Pepper,
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct EnemyType {
+    pub health: i32,
+    /// Tiles per second
+    pub speed: f32,
+    pub sprite: String,
+    /// Size of one frame in the sprite sheet, in pixels
+    #[serde(default = "default_frame_size")]
+    pub frame_size: [f32; 2],
+    #[serde(default = "default_frame_count")]
+    pub columns: usize,
+    #[serde(default = "default_frame_count")]
+    pub rows: usize,
+    #[serde(default)]
+    pub animation: Option<EnemyAnimation>,
+    /// What the enemy breaks into when it dies
+    #[serde(default)]
+    pub split: Option<EnemySplit>,
+    /// Money paid out for a kill
+    #[serde(default)]
+    pub reward: u32,
+    /// Lives lost when the enemy reaches the end of its path
+    #[serde(default = "default_damage")]
+    pub damage: u32,
 }
 
-impl EnemyKind {
-    fn initial_health(&self) -> i32 {
-        match self {
-            EnemyKind::Potato => 1,
-            EnemyKind::Carrot => 1,
-            EnemyKind::Pepper => 3,
-        }
+impl EnemyType {
+    pub fn atlas(&self, asset_server: &Res<AssetServer>) -> TextureAtlas {
+        let texture_handle = asset_server.load(self.sprite.as_str());
+
+        TextureAtlas::from_grid(
+            texture_handle,
+            Vec2::from_array(self.frame_size),
+            self.columns,
+            self.rows,
+            None,
+            None,
+        )
     }
 }
 
+/// Sprite sheet frames `first..=last`, each shown for `frame_time` seconds
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+pub struct EnemyAnimation {
+    pub first: usize,
+    pub last: usize,
+    pub frame_time: f32,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct EnemySplit {
+    pub count: usize,
+    pub kind: EnemyKind,
+}
+
+fn default_frame_size() -> [f32; 2] {
+    [32., 32.]
+}
+
+fn default_frame_count() -> usize {
+    1
+}
+
+fn default_damage() -> u32 {
+    1
+}
+
+#[derive(Resource, Debug)]
+pub struct EnemyTableHandle(pub Handle<EnemyTable>);
+
 #[derive(Resource, Default, Debug)]
+pub struct Paths(pub Vec<Path>);
+
+/// Path graph. The main tiles come first in order, branch tiles are appended after them.
+#[derive(Default, Debug)]
 pub struct Path {
     pub start_position: Vec2,
     pub end_position: Vec2,
     pub positions: VecDeque<PathNode>,
 }
 
+impl Path {
+    pub fn new(tiles: &[[f32; 2]], forks: &[&Fork]) -> Self {
+        let to_world = |pos: &[f32; 2]| GridPos::from_tiles(*pos).center();
+
+        let mut positions: VecDeque<PathNode> = tiles
+            .iter()
+            .map(|pos| PathNode::new(to_world(pos)))
+            .collect();
+        for idx in 1..positions.len() {
+            positions[idx - 1].next.push((idx, 1.));
+        }
+
+        for fork in forks {
+            // Broken forks are reported by `Level::validate`, the editor still has to draw them
+            if fork.at + 1 >= tiles.len() {
+                continue;
+            }
+
+            // The main path is always the first way out of a fork
+            positions[fork.at].rule = fork.rule;
+            positions[fork.at].next[0].1 = fork.weight;
+
+            for branch in fork.branches.iter().filter(|b| b.rejoin < tiles.len()) {
+                let first = positions.len();
+                for (i, pos) in branch.tiles.iter().enumerate() {
+                    let mut node = PathNode::new(to_world(pos));
+                    node.next.push(if i + 1 < branch.tiles.len() {
+                        (first + i + 1, 1.)
+                    } else {
+                        (branch.rejoin, 1.)
+                    });
+                    positions.push_back(node);
+                }
+
+                let entry = if branch.tiles.is_empty() {
+                    branch.rejoin
+                } else {
+                    first
+                };
+                positions[fork.at].next.push((entry, branch.weight));
+            }
+        }
+
+        let mut path = Self {
+            start_position: tiles.first().map(to_world).unwrap_or_default(),
+            end_position: tiles.last().map(to_world).unwrap_or_default(),
+            positions,
+        };
+        path.update_distances();
+        path
+    }
+
+    /// Path straight along `cells`, like a way through a maze
+    pub fn from_cells(cells: &[IVec2]) -> Self {
+        let tiles: Vec<[f32; 2]> = cells.iter().map(|cell| cell.as_vec2().to_array()).collect();
+        Self::new(&tiles, &[])
+    }
+
+    fn update_distances(&mut self) {
+        for node in self.positions.iter_mut() {
+            node.distance = if node.next.is_empty() {
+                0.
+            } else {
+                f32::INFINITY
+            };
+        }
+
+        // Branches only lead forward, so this settles after a few passes
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for idx in 0..self.positions.len() {
+                let node = &self.positions[idx];
+                let distance = node
+                    .next
+                    .iter()
+                    .map(|(next, _)| {
+                        let next = &self.positions[*next];
+                        node.position.distance(next.position) + next.distance
+                    })
+                    .fold(f32::INFINITY, f32::min);
+
+                if distance < self.positions[idx].distance {
+                    self.positions[idx].distance = distance;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /// Picks the node to walk to after reaching `idx`, `None` at the end of the path
+    pub fn choose_next(&mut self, idx: usize, rng: &mut impl Rng) -> Option<usize> {
+        let node = &self.positions[idx];
+        let choice = match node.next.len() {
+            0 => return None,
+            1 => 0,
+            len => match node.rule {
+                ForkRule::Random => WeightedIndex::new(node.next.iter().map(|(_, weight)| *weight))
+                    .map(|weights| weights.sample(rng))
+                    .unwrap_or(0),
+                ForkRule::Alternate => node.alternate % len,
+                ForkRule::Shortest => node
+                    .next
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (next, _))| {
+                        let next = &self.positions[*next];
+                        (i, node.position.distance(next.position) + next.distance)
+                    })
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map(|(i, _)| i)
+                    .unwrap_or(0),
+            },
+        };
+
+        let node = &mut self.positions[idx];
+        node.alternate += 1;
+        Some(node.next[choice].0)
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct PathNode {
     pub position: Vec2,
+    /// Ways out of this node as `(node index, weight)`
+    pub next: Vec<(usize, f32)>,
+    pub rule: ForkRule,
+    pub alternate: usize,
+    /// Shortest remaining distance to the end of the path
+    pub distance: f32,
 }
 
 impl PathNode {
     pub fn new(position: Vec2) -> Self {
-        Self { position }
+        Self {
+            position,
+            ..default()
+        }
     }
 }
 
@@ -512,30 +2184,316 @@ pub struct Menu {
     pub current_item: MenuItem,
 }
 
-#[derive(Debug, Resource)]
+#[derive(Debug, Resource, Default)]
 pub struct RoundCounter(pub usize);
 
-impl RoundCounter {
-    pub fn default() -> Self {
-        Self(0)
+/// Lives left in the current level, the game is over at zero
+#[derive(Debug, Resource, Default)]
+pub struct Lives(pub u32);
+
+#[derive(Debug, Resource, Default)]
+pub struct Money(pub u32);
+
+/// How many times faster than real time the level plays
+#[derive(Debug, Resource, Clone, Copy, PartialEq)]
+pub struct GameSpeed(pub f32);
+
+impl Default for GameSpeed {
+    fn default() -> Self {
+        Self(1.)
+    }
+}
+
+impl GameSpeed {
+    /// 1x, 2x, 4x and back to 1x
+    pub fn next(self) -> Self {
+        match self.0 {
+            speed if speed < 2. => Self(2.),
+            speed if speed < 4. => Self(4.),
+            _ => Self(1.),
+        }
     }
+}
 
+impl RoundCounter {
     pub fn next(&mut self) {
         self.0 += 1
     }
 }
 
-#[derive(Debug, Resource)]
-pub struct RoundList(pub Vec<Vec<(usize, EnemyKind)>>);
+#[derive(Debug, Resource, Default)]
+pub struct RoundList {
+    pub waves: Vec<Vec<WaveGroup>>,
+    pub endless: Option<Endless>,
+}
 
-#[derive(Debug, Resource)]
-pub struct Round(pub Vec<EnemyKind>);
+impl RoundList {
+    /// Groups of the 1-based `round`, scaled by the endless rule past the last wave
+    pub fn round(&self, round: usize) -> Option<Vec<WaveGroup>> {
+        if let Some(groups) = self.waves.get(round.checked_sub(1)?) {
+            return Some(groups.clone());
+        }
+
+        let endless = self.endless?;
+        let extra = (round - self.waves.len()) as i32;
+        let last = self.waves.last()?;
+
+        Some(
+            last.iter()
+                .map(|group| WaveGroup {
+                    count: group.count + (endless.count_growth * extra as f32).round() as usize,
+                    interval: group.interval * endless.interval_scale.powi(extra),
+                    ..group.clone()
+                })
+                .collect(),
+        )
+    }
+}
 
+/// Groups still waiting to be spawned this round, front first
+#[derive(Debug, Resource, Default)]
+pub struct Round(pub VecDeque<WaveGroup>);
+
+/// Counts down to the next spawn of the group at the front of `Round`
 #[derive(Debug, Resource)]
 pub struct SpawnTimer(pub Timer);
 
-impl SpawnTimer {
-    pub fn default() -> Self {
-        Self(Timer::new(Duration::from_secs(10), TimerMode::Repeating))
+impl Default for SpawnTimer {
+    fn default() -> Self {
+        Self(Timer::new(Duration::ZERO, TimerMode::Once))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A 10x10 map, tiles from -5 up to 4 on both axes, with one potato to beat
+    fn level(paths: serde_json::Value, decor: serde_json::Value) -> Level {
+        serde_json::from_value(json!({
+            "paths": paths,
+            "decor": decor,
+            "width": 10,
+            "height": 10,
+            "waves": [[{ "kind": "Potato", "count": 1, "interval": 1.0 }]],
+        }))
+        .unwrap()
+    }
+
+    fn potatoes() -> EnemyTable {
+        serde_json::from_value(json!({
+            "Potato": { "health": 1, "speed": 1.0, "sprite": "potato.png", "reward": 1 },
+        }))
+        .unwrap()
+    }
+
+    fn validate(level: &Level) -> Result<(), Vec<LevelError>> {
+        level.validate(&potatoes())
+    }
+
+    #[test]
+    fn level_needs_waves() {
+        let mut level = level(json!([[[0, 0], [1, 0]]]), json!([]));
+        level.waves.clear();
+        assert_eq!(validate(&level), Err(vec![LevelError::NoWaves]));
+    }
+
+    fn read_asset<T: serde::de::DeserializeOwned>(file: &str) -> T {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join(file);
+        let json = std::fs::read_to_string(&path).unwrap();
+        serde_json::from_str(&json).unwrap_or_else(|err| panic!("{file}: {err}"))
+    }
+
+    #[test]
+    fn enemy_sprite_sheets_need_frames() {
+        let level = level(json!([[[0, 0], [1, 0]]]), json!([]));
+        let enemies: EnemyTable = serde_json::from_value(json!({
+            "Potato": { "health": 1, "speed": 1.0, "sprite": "potato.png", "reward": 1 },
+            "Ghost": { "health": 1, "speed": 1.0, "sprite": "ghost.png", "reward": 1, "rows": 0 },
+        }))
+        .unwrap();
+        assert_eq!(
+            level.validate(&enemies),
+            Err(vec![LevelError::EmptySpriteSheet {
+                kind: EnemyKind("Ghost".to_string()),
+                columns: 1,
+                rows: 0,
+            }])
+        );
+    }
+
+    #[test]
+    fn every_upgrade_changes_the_gun_sprite() {
+        for gun in [Gun::Gun1, Gun::Gun2] {
+            for tier in 1..=gun.upgrades().len() {
+                assert_ne!(gun.sprite_path(tier), gun.sprite_path(tier - 1), "{gun:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn shipped_levels_are_valid() {
+        let enemies: EnemyTable = read_asset("main.enemies.json");
+        let campaign: Campaign = read_asset("main.campaign.json");
+        for entry in &campaign.levels {
+            let level: Level = read_asset(&entry.file);
+            assert_eq!(level.validate(&enemies), Ok(()), "{}", entry.file);
+        }
+    }
+
+    // Same values, but `original` may spell out fields that are at their default
+    fn leaves_out_defaults_of(saved: &serde_json::Value, original: &serde_json::Value) -> bool {
+        use serde_json::Value;
+        match (saved, original) {
+            (Value::Object(saved), Value::Object(original)) => saved.iter().all(|(key, value)| {
+                original
+                    .get(key)
+                    .is_some_and(|o| leaves_out_defaults_of(value, o))
+            }),
+            (Value::Array(saved), Value::Array(original)) => {
+                saved.len() == original.len()
+                    && saved
+                        .iter()
+                        .zip(original)
+                        .all(|(s, o)| leaves_out_defaults_of(s, o))
+            }
+            _ => saved == original,
+        }
+    }
+
+    #[test]
+    fn saving_a_shipped_level_keeps_its_layout() {
+        let campaign: Campaign = read_asset("main.campaign.json");
+        for entry in &campaign.levels {
+            let level: Level = read_asset(&entry.file);
+            let saved: serde_json::Value =
+                serde_json::from_str(&serde_json::to_string(&level).unwrap()).unwrap();
+            let original: serde_json::Value = read_asset(&entry.file);
+            assert!(leaves_out_defaults_of(&saved, &original), "{}", entry.file);
+        }
+    }
+
+    #[test]
+    fn connected_path_is_valid() {
+        let level = level(json!([[[-2, 0], [-1, 0], [0, 0], [0, 1]]]), json!([]));
+        assert_eq!(validate(&level), Ok(()));
+    }
+
+    #[test]
+    fn single_path_from_older_level_files() {
+        let level: Level = serde_json::from_value(json!({
+            "path": [[0, 0], [1, 0]],
+            "decor": [],
+        }))
+        .unwrap();
+        assert_eq!(level.paths, [vec![[0., 0.], [1., 0.]]]);
+    }
+
+    #[test]
+    fn gap_between_tiles() {
+        let level = level(json!([[[0, 0], [2, 0], [3, 0]]]), json!([]));
+        assert_eq!(
+            validate(&level),
+            Err(vec![LevelError::Gap {
+                path: 0,
+                index: 1,
+                from: ivec2(0, 0),
+                to: ivec2(2, 0),
+            }])
+        );
+    }
+
+    #[test]
+    fn repeated_tile() {
+        let level = level(json!([[[0, 0], [0, 0], [1, 0]]]), json!([]));
+        assert_eq!(
+            validate(&level),
+            Err(vec![LevelError::DuplicateTile {
+                path: 0,
+                index: 1,
+                first: 0,
+                pos: ivec2(0, 0),
+            }])
+        );
+    }
+
+    #[test]
+    fn path_may_cross_itself_straight_across() {
+        // Right through [0, 0], around a loop and back down through it
+        let level = level(
+            json!([[
+                [-2, 0],
+                [-1, 0],
+                [0, 0],
+                [1, 0],
+                [1, 1],
+                [0, 1],
+                [0, 0],
+                [0, -1],
+                [0, -2]
+            ]]),
+            json!([]),
+        );
+        assert_eq!(validate(&level), Ok(()));
+    }
+
+    #[test]
+    fn path_may_not_turn_on_its_own_tile() {
+        // Same loop, but it ends on [0, 0] instead of passing through
+        let level = level(
+            json!([[[-2, 0], [-1, 0], [0, 0], [1, 0], [1, 1], [0, 1], [0, 0]]]),
+            json!([]),
+        );
+        assert_eq!(
+            validate(&level),
+            Err(vec![LevelError::DuplicateTile {
+                path: 0,
+                index: 6,
+                first: 2,
+                pos: ivec2(0, 0),
+            }])
+        );
+    }
+
+    #[test]
+    fn tiles_outside_the_map() {
+        let level = level(
+            json!([[[4, 0], [5, 0]]]),
+            json!([["rock.png", [0, -6], false]]),
+        );
+        assert_eq!(
+            validate(&level),
+            Err(vec![
+                LevelError::PathOutOfBounds {
+                    path: 0,
+                    index: 1,
+                    pos: ivec2(5, 0),
+                },
+                LevelError::DecorOutOfBounds {
+                    index: 0,
+                    pos: ivec2(0, -6),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn decor_on_a_path() {
+        let level = level(
+            json!([[[0, 0], [1, 0], [2, 0]]]),
+            json!([["rock.png", [2, 3], false], ["bush.png", [1.5, 0.5], false]]),
+        );
+        assert_eq!(
+            validate(&level),
+            Err(vec![LevelError::DecorOnPath {
+                index: 1,
+                image: "bush.png".to_string(),
+                pos: ivec2(1, 0),
+            }])
+        );
     }
 }
@@ -0,0 +1,342 @@
+use std::fs;
+
+use bevy::{asset::FileAssetIo, math::*, prelude::*};
+use serde::Serialize;
+
+use crate::components::*;
+use crate::systems::{build_level, cursor_cell, focus_camera};
+use crate::*;
+
+pub const CAMERA_PAN_SPEED: f32 = 12. * TILE_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorTool {
+    Path,
+    Decor,
+}
+
+/// Working copy of the level open in the editor
+#[derive(Resource, Debug)]
+pub struct Editor {
+    pub level: Level,
+    pub tool: EditorTool,
+    /// Path that new tiles go to, one past the last path starts a new one
+    pub path: usize,
+    /// Decor textures found in `assets/resources`
+    pub textures: Vec<String>,
+    pub texture: usize,
+    pub status: String,
+}
+
+impl Editor {
+    pub fn new(level: Level) -> Self {
+        Self {
+            level,
+            tool: EditorTool::Path,
+            path: 0,
+            textures: decor_textures(),
+            texture: 0,
+            status: String::new(),
+        }
+    }
+
+    pub fn texture(&self) -> Option<&String> {
+        self.textures.get(self.texture)
+    }
+}
+
+fn decor_textures() -> Vec<String> {
+    let dir = FileAssetIo::get_base_path().join("assets/resources");
+    let mut textures: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".png"))
+        .map(|name| format!("resources/{name}"))
+        .collect();
+    textures.sort();
+    textures
+}
+
+// Writes the level out in the same layout as the hand written level files
+fn write_level(level: &Level, file: &std::path::Path) -> std::io::Result<()> {
+    let mut json = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+    level.serialize(&mut serde_json::Serializer::with_formatter(
+        &mut json, formatter,
+    ))?;
+    fs::write(FileAssetIo::get_base_path().join("assets").join(file), json)
+}
+
+#[allow(clippy::type_complexity)]
+pub fn open_editor(
+    mut commands: Commands,
+    level: Res<LevelHandle>,
+    levels: Res<Assets<Level>>,
+    mut state: ResMut<State<AppState>>,
+    entity_q: Query<Entity, Or<(With<Enemy>, With<Projectile>)>>,
+    keys: Res<Input<KeyCode>>,
+) {
+    if !keys.just_pressed(KeyCode::F2) {
+        return;
+    }
+
+    let Some(level) = levels.get(&level.0) else {
+        return;
+    };
+
+    // The paths get rebuilt while editing, so the current round starts over afterwards
+    for entity in &entity_q {
+        commands.entity(entity).despawn();
+    }
+
+    commands.insert_resource(Editor::new(level.clone()));
+    state.set(AppState::Editor).unwrap();
+}
+
+pub fn show_editor(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/ComicMono.ttf");
+    commands.spawn(EditorBundle::new(font));
+}
+
+pub fn drop_editor(mut commands: Commands) {
+    commands.remove_resource::<Editor>();
+}
+
+/// Applies the edits to the level and goes back to playing it.
+/// `reload_level` picks the change up like any other level change.
+pub fn close_editor(
+    mut editor: ResMut<Editor>,
+    level: Res<LevelHandle>,
+    mut levels: ResMut<Assets<Level>>,
+    enemy_table: Res<EnemyTableHandle>,
+    enemy_tables: Res<Assets<EnemyTable>>,
+    mut state: ResMut<State<AppState>>,
+    keys: Res<Input<KeyCode>>,
+) {
+    if !keys.just_pressed(KeyCode::F2) {
+        return;
+    }
+
+    let Some(enemies) = enemy_tables.get(&enemy_table.0) else {
+        return;
+    };
+    if let Err(errors) = editor.level.validate(enemies) {
+        editor.status = format!("can't play yet: {}", errors[0]);
+        return;
+    }
+
+    if let Some(level) = levels.get_mut(&level.0) {
+        *level = editor.level.clone();
+    }
+    state.set(AppState::Level).unwrap();
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn edit_level(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut editor: ResMut<Editor>,
+    windows: Res<Windows>,
+    camera_q: Query<&Transform, With<Camera>>,
+    level_q: Query<Entity, With<LevelEntity>>,
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mut occupancy: ResMut<Occupancy>,
+    seed_override: Res<SeedOverride>,
+) {
+    let keys_map = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    if let Some(idx) = keys_map.iter().position(|key| keys.just_pressed(*key)) {
+        editor.path = idx.min(editor.level.paths.len());
+    }
+
+    if keys.just_pressed(KeyCode::Tab) {
+        editor.tool = match editor.tool {
+            EditorTool::Path => EditorTool::Decor,
+            EditorTool::Decor => EditorTool::Path,
+        };
+    }
+
+    if !editor.textures.is_empty() {
+        let len = editor.textures.len();
+        if keys.just_pressed(KeyCode::Q) {
+            editor.texture = (editor.texture + len - 1) % len;
+        }
+        if keys.just_pressed(KeyCode::E) {
+            editor.texture = (editor.texture + 1) % len;
+        }
+    }
+
+    let window = windows.get_primary().unwrap();
+    let Some(GridPos(cell)) = cursor_cell(window, camera_q.single()) else {
+        return;
+    };
+    let pos = [cell.x as f32, cell.y as f32];
+
+    if keys.just_pressed(KeyCode::C) {
+        editor.level.center_pos = Some(pos);
+        editor.status = format!("camera center set to {pos:?}");
+    }
+
+    let mut changed = false;
+    match editor.tool {
+        EditorTool::Path => {
+            let path = editor.path;
+            if buttons.pressed(MouseButton::Left) {
+                if path == editor.level.paths.len() {
+                    editor.level.paths.push(Vec::new());
+                }
+                let tiles = &mut editor.level.paths[path];
+                if tiles.last() != Some(&pos) {
+                    tiles.push(pos);
+                    changed = true;
+                }
+            }
+            if buttons.just_pressed(MouseButton::Right) {
+                if let Some(tiles) = editor.level.paths.get_mut(path) {
+                    changed = tiles.pop().is_some();
+                }
+            }
+        }
+        EditorTool::Decor => {
+            let decor_idx = editor
+                .level
+                .decor
+                .iter()
+                .position(|(_, decor_pos, _)| decor_pos == &pos);
+
+            if buttons.just_pressed(MouseButton::Left) {
+                if let Some(texture) = editor.texture().cloned() {
+                    match decor_idx {
+                        Some(idx) => editor.level.decor[idx].0 = texture,
+                        None => editor.level.decor.push((texture, pos, true)),
+                    }
+                    changed = true;
+                }
+            }
+            if let Some(idx) = decor_idx {
+                if buttons.just_pressed(MouseButton::Right) {
+                    editor.level.decor.remove(idx);
+                    changed = true;
+                } else if keys.just_pressed(KeyCode::B) {
+                    let blocks = &mut editor.level.decor[idx].2;
+                    *blocks = !*blocks;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    if changed {
+        for entity in &level_q {
+            commands.entity(entity).despawn();
+        }
+        let seed = seed_override.seed(&editor.level);
+        build_level(
+            &mut commands,
+            &asset_server,
+            &mut occupancy,
+            &editor.level,
+            seed,
+        );
+    }
+}
+
+pub fn pan_camera(
+    mut camera_q: Query<&mut Transform, With<Camera>>,
+    editor: Res<Editor>,
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+) {
+    let mut camera_transform = camera_q.single_mut();
+    if keys.just_pressed(KeyCode::Home) {
+        focus_camera(&mut camera_transform, &editor.level);
+        return;
+    }
+
+    let mut direction = Vec2::ZERO;
+    if keys.pressed(KeyCode::Left) {
+        direction.x -= 1.;
+    }
+    if keys.pressed(KeyCode::Right) {
+        direction.x += 1.;
+    }
+    if keys.pressed(KeyCode::Down) {
+        direction.y -= 1.;
+    }
+    if keys.pressed(KeyCode::Up) {
+        direction.y += 1.;
+    }
+    camera_transform.translation +=
+        (direction * CAMERA_PAN_SPEED * time.delta_seconds()).extend(0.);
+}
+
+pub fn save_level(
+    mut editor: ResMut<Editor>,
+    asset_server: Res<AssetServer>,
+    level: Res<LevelHandle>,
+    enemy_table: Res<EnemyTableHandle>,
+    enemy_tables: Res<Assets<EnemyTable>>,
+    keys: Res<Input<KeyCode>>,
+) {
+    if !keys.just_pressed(KeyCode::S) || !keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        return;
+    }
+
+    let Some(file) = asset_server
+        .get_handle_path(&level.0)
+        .map(|handle_path| handle_path.path().to_path_buf())
+    else {
+        return;
+    };
+
+    editor.status = match write_level(&editor.level, &file) {
+        Ok(()) => match enemy_tables
+            .get(&enemy_table.0)
+            .map(|enemies| editor.level.validate(enemies))
+        {
+            Some(Ok(())) | None => format!("saved {}", file.display()),
+            Some(Err(errors)) => format!(
+                "saved {} with {} problem(s), first: {}",
+                file.display(),
+                errors.len(),
+                errors[0]
+            ),
+        },
+        Err(err) => {
+            error!("editor: could not save {}: {err}", file.display());
+            format!("could not save {}: {err}", file.display())
+        }
+    };
+}
+
+pub fn update_editor_text(editor: Res<Editor>, mut query: Query<&mut Text, With<EditorText>>) {
+    if !editor.is_changed() {
+        return;
+    }
+
+    if let Ok(mut text) = query.get_single_mut() {
+        text.sections[0].value = match editor.tool {
+            EditorTool::Path => {
+                let tiles = editor.level.paths.get(editor.path).map_or(0, Vec::len);
+                format!("Path {}: {tiles} tiles\n", editor.path + 1)
+            }
+            EditorTool::Decor => format!(
+                "Decor: {}\n",
+                editor.texture().map_or("no textures", String::as_str)
+            ),
+        };
+        text.sections[1].value = format!("{}\n", editor.status);
+    }
+}
@@ -0,0 +1,317 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use bevy::{math::*, prelude::*};
+
+use crate::*;
+
+/// A cell of the map grid. Cell `(x, y)` covers the world positions from
+/// `(x, y) * TILE_SIZE` up to but not including `(x + 1, y + 1) * TILE_SIZE`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GridPos(pub IVec2);
+
+impl GridPos {
+    pub fn new(x: i32, y: i32) -> Self {
+        Self(IVec2::new(x, y))
+    }
+
+    /// Cell containing a world position
+    pub fn from_world(position: Vec2) -> Self {
+        Self((position / TILE_SIZE).floor().as_ivec2())
+    }
+
+    /// Cell of a position given in tiles, like the ones in level files
+    pub fn from_tiles(position: [f32; 2]) -> Self {
+        Self(Vec2::from_array(position).floor().as_ivec2())
+    }
+
+    /// Cell under a position in the window, see `window_to_world`
+    pub fn from_window(position: Vec2, window_size: Vec2, camera: &Transform) -> Self {
+        Self::from_world(window_to_world(position, window_size, camera))
+    }
+
+    /// World position of the middle of the cell
+    pub fn center(self) -> Vec2 {
+        self.corner() + Vec2::splat(TILE_SIZE / 2.)
+    }
+
+    /// World position of the lower left corner of the cell
+    pub fn corner(self) -> Vec2 {
+        self.0.as_vec2() * TILE_SIZE
+    }
+
+    /// Window position of the middle of the cell
+    pub fn to_window(self, window_size: Vec2, camera: &Transform) -> Vec2 {
+        world_to_window(self.center(), window_size, camera)
+    }
+}
+
+/// Converts a window position, with the origin in the lower left corner as
+/// `Window::cursor_position` gives it, to world units.
+/// The camera's scale is its zoom, 2 shows twice as much of the world.
+pub fn window_to_world(position: Vec2, window_size: Vec2, camera: &Transform) -> Vec2 {
+    (position - window_size / 2.) * camera.scale.truncate() + camera.translation.truncate()
+}
+
+pub fn world_to_window(position: Vec2, window_size: Vec2, camera: &Transform) -> Vec2 {
+    (position - camera.translation.truncate()) / camera.scale.truncate() + window_size / 2.
+}
+
+/// Lower left cell of a footprint of `size` tiles placed around `position` (world units).
+/// Odd sizes are centered on the cell under `position`,
+/// even sizes on the grid corner closest to it.
+pub fn footprint_min(position: Vec2, size: IVec2) -> GridPos {
+    GridPos(
+        (position / TILE_SIZE - size.as_vec2() / 2. + 0.5)
+            .floor()
+            .as_ivec2(),
+    )
+}
+
+/// World position a footprint of `size` tiles snaps to around `position`
+pub fn snap_footprint(position: Vec2, size: IVec2) -> Vec2 {
+    footprint_min(position, size).corner() + size.as_vec2() * TILE_SIZE / 2.
+}
+
+/// Grid cells covered by a footprint of `size` tiles centered on `center` (world units)
+pub fn footprint_cells(center: Vec2, size: IVec2) -> impl Iterator<Item = IVec2> {
+    let min = footprint_min(center, size).0;
+    (0..size.y).flat_map(move |y| (0..size.x).map(move |x| min + IVec2::new(x, y)))
+}
+
+/// Shortest walk from `start` to `goal` between side by side cells, both ends included.
+/// A* over the grid, `None` when the cells `walkable` rules out cut `goal` off.
+pub fn find_path(
+    start: IVec2,
+    goal: IVec2,
+    walkable: impl Fn(IVec2) -> bool,
+) -> Option<Vec<IVec2>> {
+    let estimate = |cell: IVec2| (goal - cell).abs().to_array().iter().sum::<i32>();
+
+    let mut open = BinaryHeap::from([Reverse((estimate(start), 0, start.to_array()))]);
+    let mut cost = HashMap::from([(start, 0)]);
+    let mut came_from: HashMap<IVec2, IVec2> = HashMap::new();
+
+    while let Some(Reverse((_, steps, cell))) = open.pop() {
+        let cell = IVec2::from_array(cell);
+        if cell == goal {
+            let mut path = vec![goal];
+            while let Some(prev) = came_from.get(path.last().unwrap()) {
+                path.push(*prev);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        // Already reached with fewer steps
+        if cost[&cell] < steps {
+            continue;
+        }
+
+        for dir in [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y] {
+            let next = cell + dir;
+            if !walkable(next) || cost.get(&next).is_some_and(|&known| known <= steps + 1) {
+                continue;
+            }
+            cost.insert(next, steps + 1);
+            came_from.insert(next, cell);
+            open.push(Reverse((
+                steps + 1 + estimate(next),
+                steps + 1,
+                next.to_array(),
+            )));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(x: f32, y: f32, zoom: f32) -> Transform {
+        Transform::from_xyz(x, y, 999.).with_scale(Vec3::new(zoom, zoom, 1.))
+    }
+
+    #[test]
+    fn world_cells_floor_towards_negative() {
+        assert_eq!(GridPos::from_world(Vec2::ZERO), GridPos::new(0, 0));
+        assert_eq!(
+            GridPos::from_world(Vec2::splat(TILE_SIZE - 0.01)),
+            GridPos::new(0, 0)
+        );
+        assert_eq!(
+            GridPos::from_world(Vec2::splat(TILE_SIZE)),
+            GridPos::new(1, 1)
+        );
+        assert_eq!(
+            GridPos::from_world(Vec2::splat(-0.01)),
+            GridPos::new(-1, -1)
+        );
+        assert_eq!(
+            GridPos::from_world(vec2(-TILE_SIZE, 2.5 * TILE_SIZE)),
+            GridPos::new(-1, 2)
+        );
+    }
+
+    #[test]
+    fn cell_center_maps_back_to_cell() {
+        for cell in [
+            GridPos::new(0, 0),
+            GridPos::new(-3, 7),
+            GridPos::new(-16, -16),
+        ] {
+            assert_eq!(GridPos::from_world(cell.center()), cell);
+            assert_eq!(GridPos::from_world(cell.corner()), cell);
+        }
+        assert_eq!(GridPos::new(-1, 2).center(), vec2(-0.5, 2.5) * TILE_SIZE);
+    }
+
+    #[test]
+    fn tiles_match_level_files() {
+        assert_eq!(GridPos::from_tiles([3., -2.]), GridPos::new(3, -2));
+        assert_eq!(GridPos::from_tiles([-0.5, 0.5]), GridPos::new(-1, 0));
+    }
+
+    #[test]
+    fn window_center_is_camera_position() {
+        let window_size = vec2(1280., 720.);
+        let camera = camera(100., -40., 1.);
+        assert_eq!(
+            window_to_world(window_size / 2., window_size, &camera),
+            vec2(100., -40.)
+        );
+        assert_eq!(
+            window_to_world(Vec2::ZERO, window_size, &camera),
+            vec2(-540., -400.)
+        );
+    }
+
+    #[test]
+    fn window_conversion_zooms_around_the_camera() {
+        let window_size = vec2(800., 600.);
+        let camera = camera(TILE_SIZE, 0., 2.);
+        // 100 pixels right of the center shows 200 world units
+        assert_eq!(
+            window_to_world(vec2(500., 300.), window_size, &camera),
+            vec2(TILE_SIZE + 200., 0.)
+        );
+        assert_eq!(
+            GridPos::from_window(vec2(500., 300.), window_size, &camera),
+            GridPos::from_world(vec2(TILE_SIZE + 200., 0.))
+        );
+    }
+
+    #[test]
+    fn window_conversion_round_trips() {
+        let window_size = vec2(1280., 720.);
+        for camera in [
+            camera(0., 0., 1.),
+            camera(-333., 75., 0.5),
+            camera(64., 64., 3.),
+        ] {
+            for cell in [
+                GridPos::new(0, 0),
+                GridPos::new(-5, 4),
+                GridPos::new(12, -9),
+            ] {
+                let window_pos = cell.to_window(window_size, &camera);
+                assert_eq!(GridPos::from_window(window_pos, window_size, &camera), cell);
+            }
+        }
+    }
+
+    #[test]
+    fn odd_footprints_center_on_the_hovered_cell() {
+        let position = vec2(0.3, -0.7) * TILE_SIZE;
+        assert_eq!(
+            snap_footprint(position, IVec2::ONE),
+            vec2(0.5, -0.5) * TILE_SIZE
+        );
+        assert_eq!(footprint_min(position, IVec2::ONE), GridPos::new(0, -1));
+        assert_eq!(
+            footprint_min(position, IVec2::splat(3)),
+            GridPos::new(-1, -2)
+        );
+        assert_eq!(
+            snap_footprint(position, IVec2::splat(3)),
+            vec2(0.5, -0.5) * TILE_SIZE
+        );
+    }
+
+    #[test]
+    fn even_footprints_snap_to_the_closest_corner() {
+        let size = IVec2::splat(2);
+        assert_eq!(snap_footprint(vec2(0.3, 0.3) * TILE_SIZE, size), Vec2::ZERO);
+        assert_eq!(
+            snap_footprint(vec2(0.6, 0.3) * TILE_SIZE, size),
+            vec2(TILE_SIZE, 0.)
+        );
+        assert_eq!(
+            snap_footprint(vec2(-0.4, -0.6) * TILE_SIZE, size),
+            vec2(0., -TILE_SIZE)
+        );
+        assert_eq!(footprint_min(Vec2::ZERO, size), GridPos::new(-1, -1));
+    }
+
+    #[test]
+    fn footprint_cells_cover_the_snapped_area() {
+        let cells: Vec<IVec2> = footprint_cells(Vec2::ZERO, IVec2::splat(2)).collect();
+        assert_eq!(
+            cells,
+            [ivec2(-1, -1), ivec2(0, -1), ivec2(-1, 0), ivec2(0, 0)]
+        );
+
+        let center = GridPos::new(4, -2).center();
+        let cells: Vec<IVec2> = footprint_cells(center, IVec2::ONE).collect();
+        assert_eq!(cells, [ivec2(4, -2)]);
+
+        for size in [IVec2::ONE, IVec2::splat(2), IVec2::splat(3)] {
+            let center = snap_footprint(vec2(-1.2, 3.9) * TILE_SIZE, size);
+            assert_eq!(
+                footprint_cells(center, size).count() as i32,
+                size.x * size.y
+            );
+            assert_eq!(
+                footprint_cells(center, size).next(),
+                Some(footprint_min(center, size).0)
+            );
+        }
+    }
+
+    fn walls(cells: &[IVec2]) -> impl Fn(IVec2) -> bool + '_ {
+        |cell| cell.abs().max_element() <= 4 && !cells.contains(&cell)
+    }
+
+    #[test]
+    fn path_goes_straight_when_open() {
+        let path = find_path(ivec2(-2, 0), ivec2(2, 0), walls(&[])).unwrap();
+        assert_eq!(path.len(), 5);
+        assert_eq!(path.first(), Some(&ivec2(-2, 0)));
+        assert_eq!(path.last(), Some(&ivec2(2, 0)));
+        assert_eq!(
+            find_path(ivec2(1, 1), ivec2(1, 1), walls(&[])),
+            Some(vec![ivec2(1, 1)])
+        );
+    }
+
+    #[test]
+    fn path_walks_around_walls_one_step_at_a_time() {
+        let wall: Vec<IVec2> = (-4..=2).map(|y| ivec2(0, y)).collect();
+        let path = find_path(ivec2(-2, 0), ivec2(2, 0), walls(&wall)).unwrap();
+        // 5 steps up to the gap at y = 3 and 5 back down
+        assert_eq!(path.len(), 11);
+        assert!(path.contains(&ivec2(0, 3)));
+        for step in path.windows(2) {
+            assert_eq!((step[1] - step[0]).abs().to_array().iter().sum::<i32>(), 1);
+        }
+    }
+
+    #[test]
+    fn no_path_through_a_closed_wall() {
+        let wall: Vec<IVec2> = (-4..=4).map(|y| ivec2(0, y)).collect();
+        assert_eq!(find_path(ivec2(-2, 0), ivec2(2, 0), walls(&wall)), None);
+        // Nor onto a blocked goal
+        assert_eq!(find_path(ivec2(-2, 0), ivec2(0, 0), walls(&wall)), None);
+    }
+}
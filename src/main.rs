@@ -1,18 +1,22 @@
 use std::f32::consts::PI;
 
 use bevy::{
-    diagnostic::FrameTimeDiagnosticsPlugin, math::*, prelude::*, time::FixedTimestep,
-    window::WindowResizeConstraints,
+    diagnostic::FrameTimeDiagnosticsPlugin, ecs::schedule::ShouldRun, math::*, prelude::*,
+    time::FixedTimestep, window::WindowResizeConstraints,
 };
 
 use bevy_common_assets::json::JsonAssetPlugin;
 
 pub mod components;
+pub mod editor;
+pub mod grid;
 pub mod interpolation;
 pub mod systems;
 pub mod ui;
 
 use components::*;
+use editor::*;
+use grid::*;
 use systems::*;
 use ui::*;
 
@@ -24,6 +28,7 @@ pub const TIME_STEP: f32 = 1.0 / 360.;
 // These constants are defined in `Transform` units.
 
 pub const SCOREBOARD_FONT_SIZE: f32 = 40.0;
+pub const ERROR_FONT_SIZE: f32 = 24.0;
 pub const SCOREBOARD_TEXT_PADDING: Val = Val::Px(5.0);
 
 pub const BACKGROUND_COLOR: Color = Color::rgb(0.1, 0.1, 0.1);
@@ -32,11 +37,18 @@ pub const WALL_COLOR: Color = Color::rgb(1., 1., 1.);
 pub const TEXT_COLOR: Color = Color::rgb(0.8, 0.8, 1.8);
 pub const SCORE_COLOR: Color = Color::rgb(1.0, 0.5, 0.5);
 pub const ERROR_COLOR: Color = Color::rgb(1.0, 0., 0.);
+pub const LOCKED_COLOR: Color = Color::rgb(0.4, 0.4, 0.4);
 pub const TILE_COLOR: Color = Color::rgb(0.2, 0.2, 0.2);
 pub const START_COLOR: Color = Color::rgb(0., 1., 0.);
 pub const END_COLOR: Color = Color::rgb(1., 0., 0.);
+pub const RANGE_COLOR: Color = Color::rgba(1., 1., 1., 0.15);
+pub const IN_RANGE_COLOR: Color = Color::rgb(1.8, 1.8, 0.8);
 
-pub const MAP_SIZE: i32 = 16; // Map width and height are 2 * MAP_SIZE
+pub const STARTING_LIVES: u32 = 20;
+pub const STARTING_MONEY: u32 = 150;
+pub const SELL_REFUND: f32 = 0.5; // Part of the cost paid back when selling
+
+pub const MAP_SIZE: i32 = 16; // Default map width and height are 2 * MAP_SIZE
 pub const TILE_SIZE: f32 = 64.;
 pub const SPRITE_SIZE: f32 = 16.; // DO NOT TOUCH!!!!!
 
@@ -45,6 +57,7 @@ pub const PROJECTILE_LAYER: f32 = 6.;
 pub const BACKGROUND_LAYER: f32 = 1.;
 pub const PATH_LAYER: f32 = 2.;
 
+pub const RANGE_LAYER: f32 = 4.;
 pub const ENEMY_LAYER: f32 = 5.;
 pub const CURSOR_LAYER: f32 = 10.;
 
@@ -78,36 +91,109 @@ fn main() {
                 .set(WindowPlugin {
                     window: window_primary(),
                     ..default()
+                })
+                .set(AssetPlugin {
+                    // Lets levels reload while the game is running
+                    watch_for_changes: cfg!(debug_assertions),
+                    ..default()
                 }),
         )
-        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(FrameTimeDiagnosticsPlugin)
         .add_plugin(JsonAssetPlugin::<Level>::new(&["json"]))
+        .add_plugin(JsonAssetPlugin::<Campaign>::new(&["campaign.json"]))
+        .add_plugin(JsonAssetPlugin::<EnemyTable>::new(&["enemies.json"]))
         .insert_resource(ClearColor(BACKGROUND_COLOR))
+        .insert_resource(SeedOverride::from_args(std::env::args()))
         .insert_resource(Menu {
             current_item: MenuItem::Turret2x2,
         })
-        .insert_resource(Path::default())
+        .insert_resource(Paths::default())
+        .init_resource::<MapBounds>()
+        .init_resource::<Terrain>()
+        .init_resource::<Occupancy>()
         .insert_resource(RoundCounter::default())
-        .add_state(AppState::Loading)
+        .init_resource::<Lives>()
+        .init_resource::<Money>()
+        .init_resource::<GameSpeed>()
+        .init_resource::<SimTime>()
+        .init_resource::<GameRng>()
+        .init_resource::<LevelHandle>()
+        .insert_resource(CampaignProgress::load())
+        .add_state(AppState::LevelSelect)
         .add_startup_system(setup)
+        .add_system_set(SystemSet::on_enter(AppState::LevelSelect).with_system(clear_level))
+        .add_system_set(SystemSet::on_update(AppState::LevelSelect).with_system(level_select))
+        .add_system_set(
+            SystemSet::on_exit(AppState::LevelSelect).with_system(despawn_with::<LevelSelectText>),
+        )
+        .add_system_set(SystemSet::on_enter(AppState::Loading).with_system(clear_level))
         .add_system_set(SystemSet::on_update(AppState::Loading).with_system(spawn_level))
+        .add_system_set(
+            SystemSet::on_update(AppState::LevelError)
+                .with_system(show_level_errors)
+                .with_system(return_to_level_select),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::LevelError).with_system(despawn_with::<LevelErrorText>),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::Level)
+                .with_system(open_editor)
+                .with_system(pause_game.after(open_editor))
+                .with_system(handle_game_speed)
+                .with_system(handle_target_mode)
+                .with_system(handle_upgrade),
+        )
+        .add_system_set(SystemSet::on_enter(AppState::Paused).with_system(show_pause))
+        .add_system_set(SystemSet::on_update(AppState::Paused).with_system(resume_game))
+        .add_system_set(SystemSet::on_exit(AppState::Paused).with_system(despawn_with::<PauseText>))
+        .add_system_set(SystemSet::on_enter(AppState::GameOver).with_system(show_game_over))
+        .add_system_set(
+            SystemSet::on_update(AppState::GameOver)
+                .with_system(restart_level)
+                .with_system(return_to_level_select),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::GameOver).with_system(despawn_with::<GameOverText>),
+        )
+        .add_system_set(SystemSet::on_enter(AppState::Editor).with_system(show_editor))
+        .add_system_set(
+            SystemSet::on_update(AppState::Editor)
+                .with_system(edit_level)
+                .with_system(pan_camera)
+                .with_system(save_level)
+                .with_system(close_editor)
+                .with_system(update_editor_text),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::Editor)
+                .with_system(despawn_with::<EditorText>)
+                .with_system(drop_editor),
+        )
         .add_system_set(
             SystemSet::new()
-                .with_run_criteria(FixedTimestep::step(TIME_STEP as f64))
+                .with_run_criteria(FixedTimestep::step(TIME_STEP as f64).pipe(in_level))
+                .with_system(advance_sim_time.before(game_tick).before(handle_gunners))
                 .with_system(move_cursor)
-                .with_system(handle_collisions)
+                .with_system(handle_collisions.after(move_cursor))
                 .with_system(handle_gunners)
                 .with_system(handle_place.after(handle_collisions))
                 .with_system(handle_cursor_visibility)
                 .with_system(handle_sell)
                 .with_system(handle_shop)
                 .with_system(handle_projectiles)
+                // These three share the gameplay random stream, so they keep a fixed order
                 .with_system(handle_projectile_collisions.after(handle_projectiles))
-                .with_system(handle_enemies)
-                .with_system(animate_sprite)
-                .with_system(game_tick),
+                .with_system(handle_enemies.after(handle_projectile_collisions))
+                .with_system(game_tick.after(handle_enemies))
+                .with_system(animate_sprite.after(advance_sim_time))
+                .with_system(check_level_complete.before(game_tick)),
         )
+        .add_system(reload_level)
+        .add_system(apply_game_speed)
         .add_system(update_scoreboard)
+        .add_system(update_inspect)
+        .add_system(update_range_preview)
         .add_system(bevy::window::close_on_esc)
         .add_system(update_fps)
         .run();
@@ -115,6 +201,25 @@ fn main() {
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 pub enum AppState {
+    LevelSelect,
     Loading,
     Level,
+    LevelError,
+    Editor,
+    GameOver,
+    /// Pushed on top of `Level`, which is left as it was
+    Paused,
+}
+
+// Only lets the fixed timestep through while a level is being played.
+// Steps are still consumed in other states so they don't pile up.
+fn in_level(In(should_run): In<ShouldRun>, state: Res<State<AppState>>) -> ShouldRun {
+    if state.current() == &AppState::Level {
+        return should_run;
+    }
+
+    match should_run {
+        ShouldRun::YesAndCheckAgain | ShouldRun::NoAndCheckAgain => ShouldRun::NoAndCheckAgain,
+        ShouldRun::Yes | ShouldRun::No => ShouldRun::No,
+    }
 }
@@ -1,31 +1,37 @@
-use core::panic;
+use std::collections::{HashMap, HashSet};
 use std::{f32::consts::PI, time::Duration};
 
 use bevy::{
+    asset::LoadState,
     core_pipeline::bloom::BloomSettings,
     diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
     math::*,
     prelude::*,
-    sprite::collide_aabb::{collide, Collision},
+    sprite::{collide_aabb::collide, MaterialMesh2dBundle},
 };
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use crate::components::*;
 use crate::interpolation::ease;
 use crate::*;
 
 // Add the game's entities to our world
-pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    // level
-    let level = LevelHandle(asset_server.load("map.json"));
-    commands.insert_resource(level);
-
-    let enemylist = RoundList(vec![
-        vec![(5, EnemyKind::Potato), (3, EnemyKind::Carrot)],
-        vec![(8, EnemyKind::Potato), (5, EnemyKind::Carrot)],
-        vec![(10, EnemyKind::Carrot), (1, EnemyKind::Pepper)],
-    ]);
-    commands.insert_resource(enemylist);
+pub fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    seed_override: Res<SeedOverride>,
+) {
+    if let Some(seed) = &seed_override.invalid {
+        warn!("ignoring invalid --seed {seed}");
+    }
+
+    // Campaign
+    let campaign = CampaignHandle(asset_server.load("main.campaign.json"));
+    commands.insert_resource(campaign);
+    let enemy_table = EnemyTableHandle(asset_server.load("main.enemies.json"));
+    commands.insert_resource(enemy_table);
 
     // Camera
     commands.spawn((
@@ -45,9 +51,22 @@ pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     // Cursor
     commands.spawn((Cursor::new(), Collider(ColliderType::Cursor)));
 
+    // Range preview, scaled to the range when shown
+    commands.spawn((
+        MaterialMesh2dBundle {
+            mesh: meshes.add(shape::Circle::new(1.).into()).into(),
+            material: materials.add(ColorMaterial::from(RANGE_COLOR)),
+            transform: Transform::from_xyz(0., 0., RANGE_LAYER),
+            visibility: Visibility::INVISIBLE,
+            ..default()
+        },
+        RangePreview,
+    ));
+
     // Scoreboard
     let font = asset_server.load("fonts/ComicMono.ttf");
     commands.spawn(FPSBundle::new(font.clone()));
+    commands.spawn(InspectBundle::new(font.clone()));
     commands.spawn(GUIBundle::new(font));
 
     // commands.spawn(
@@ -98,8 +117,13 @@ pub struct AnimationIndices {
 #[derive(Component, Deref, DerefMut)]
 pub struct AnimationTimer(Timer);
 
+pub fn advance_sim_time(mut sim_time: ResMut<SimTime>) {
+    let delta = sim_time.delta();
+    sim_time.elapsed += delta;
+}
+
 pub fn animate_sprite(
-    time: Res<Time>,
+    sim_time: Res<SimTime>,
     mut query: Query<(
         &AnimationIndices,
         &mut AnimationTimer,
@@ -107,7 +131,7 @@ pub fn animate_sprite(
     )>,
 ) {
     for (indices, mut timer, mut sprite) in &mut query {
-        timer.tick(time.delta());
+        timer.tick(sim_time.delta());
         if timer.just_finished() {
             sprite.index = if sprite.index == indices.last {
                 indices.first
@@ -118,107 +142,493 @@ pub fn animate_sprite(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+pub fn level_select(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    campaign: Res<CampaignHandle>,
+    campaigns: Res<Assets<Campaign>>,
+    progress: Res<CampaignProgress>,
+    mut state: ResMut<State<AppState>>,
+    text_q: Query<Entity, With<LevelSelectText>>,
+    keys: Res<Input<KeyCode>>,
+) {
+    if asset_server.get_load_state(&campaign.0) == LoadState::Failed {
+        commands.insert_resource(LevelErrors(vec![LevelError::LoadFailed(
+            "main.campaign.json".to_string(),
+        )]));
+        state.set(AppState::LevelError).unwrap();
+        return;
+    }
+
+    let Some(campaign) = campaigns.get(&campaign.0) else {
+        return;
+    };
+
+    if text_q.is_empty() {
+        for level in &campaign.levels {
+            for name in &level.requires {
+                if !campaign.levels.iter().any(|other| &other.name == name) {
+                    warn!("campaign: {} requires unknown level {name}", level.name);
+                }
+            }
+        }
+
+        let font = asset_server.load("fonts/ComicMono.ttf");
+        commands.spawn(LevelSelectBundle::new(font, campaign, &progress));
+    }
+
+    let keys_map = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    let Some(idx) = keys_map.iter().position(|key| keys.just_pressed(*key)) else {
+        return;
+    };
+
+    if let Some(level) = campaign.levels.get(idx) {
+        if level.is_unlocked(&progress) {
+            commands.insert_resource(LevelHandle(asset_server.load(level.file.as_str())));
+            commands.insert_resource(CurrentLevel(level.clone()));
+            state.set(AppState::Loading).unwrap();
+        }
+    }
+}
+
+/// Despawns every entity with `T`, like the text of a screen that is closing
+pub fn despawn_with<T: Component>(mut commands: Commands, entity_q: Query<Entity, With<T>>) {
+    for entity in &entity_q {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Removes everything a played level left behind
+#[allow(clippy::type_complexity)]
+pub fn clear_level(
+    mut commands: Commands,
+    entity_q: Query<
+        Entity,
+        Or<(
+            With<LevelEntity>,
+            With<Enemy>,
+            With<Projectile>,
+            (With<Turret>, Without<Selected>),
+        )>,
+    >,
+    mut occupancy: ResMut<Occupancy>,
+) {
+    for entity in &entity_q {
+        commands.entity(entity).despawn_recursive();
+    }
+    *occupancy = Occupancy::default();
+}
+
+pub fn restart_level(mut state: ResMut<State<AppState>>, keys: Res<Input<KeyCode>>) {
+    // Backspace may have queued a state change this frame as well
+    if keys.just_pressed(KeyCode::R) {
+        let _ = state.set(AppState::Loading);
+    }
+}
+
+pub fn show_game_over(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/ComicMono.ttf");
+    commands.spawn(GameOverBundle::new(font));
+}
+
+/// Pauses with P, or by itself when the window loses focus
+pub fn pause_game(
+    mut state: ResMut<State<AppState>>,
+    windows: Res<Windows>,
+    keys: Res<Input<KeyCode>>,
+) {
+    let focused = windows.get_primary().is_none_or(Window::is_focused);
+    if keys.just_pressed(KeyCode::P) || !focused {
+        // Fails when the level is already being left this frame, which is fine
+        let _ = state.push(AppState::Paused);
+    }
+}
+
+/// Fast-forwards with F
+pub fn handle_game_speed(mut speed: ResMut<GameSpeed>, keys: Res<Input<KeyCode>>) {
+    if keys.just_pressed(KeyCode::F) {
+        *speed = speed.next();
+    }
+}
+
+/// Runs the clocks the simulation goes by at the game speed while a level is played.
+/// Only the fixed step follows `Time`, everything timed in the simulation counts fixed steps,
+/// so a level plays out the same at any speed.
+pub fn apply_game_speed(
+    speed: Res<GameSpeed>,
+    state: Res<State<AppState>>,
+    mut time: ResMut<Time>,
+) {
+    let relative_speed = match state.current() {
+        AppState::Level => speed.0,
+        _ => 1.,
+    };
+    if time.relative_speed() != relative_speed {
+        time.set_relative_speed(relative_speed);
+    }
+}
+
+pub fn resume_game(mut state: ResMut<State<AppState>>, keys: Res<Input<KeyCode>>) {
+    if keys.just_pressed(KeyCode::P) {
+        let _ = state.pop();
+    }
+}
+
+pub fn show_pause(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/ComicMono.ttf");
+    commands.spawn(PauseBundle::new(font));
+}
+
+pub fn return_to_level_select(mut state: ResMut<State<AppState>>, keys: Res<Input<KeyCode>>) {
+    if keys.just_pressed(KeyCode::Back) {
+        let _ = state.set(AppState::LevelSelect);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_level(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     level: Res<LevelHandle>,
-    mut levels: ResMut<Assets<Level>>,
+    levels: Res<Assets<Level>>,
+    enemy_table: Res<EnemyTableHandle>,
+    enemy_tables: Res<Assets<EnemyTable>>,
     mut state: ResMut<State<AppState>>,
     mut query: Query<&mut Transform, With<Camera>>,
-    mut path: ResMut<Path>,
+    mut occupancy: ResMut<Occupancy>,
+    seed_override: Res<SeedOverride>,
 ) {
-    if let Some(level) = levels.remove(level.0.id()) {
+    if asset_server.get_load_state(&enemy_table.0) == LoadState::Failed {
+        commands.insert_resource(LevelErrors(vec![LevelError::LoadFailed(
+            "main.enemies.json".to_string(),
+        )]));
+        state.set(AppState::LevelError).unwrap();
+        return;
+    }
+
+    if asset_server.get_load_state(&level.0) == LoadState::Failed {
+        let file = asset_server
+            .get_handle_path(&level.0)
+            .map(|handle_path| handle_path.path().display().to_string())
+            .unwrap_or_default();
+        commands.insert_resource(LevelErrors(vec![LevelError::LoadFailed(file)]));
+        state.set(AppState::LevelError).unwrap();
+        return;
+    }
+
+    let Some(enemies) = enemy_tables.get(&enemy_table.0) else {
+        return;
+    };
+
+    if let Some(level) = levels.get(&level.0) {
+        if let Err(errors) = level.validate(enemies) {
+            report_level_errors(&mut commands, errors);
+            state.set(AppState::LevelError).unwrap();
+            return;
+        }
+
         let mut camera_transform = query.get_single_mut().unwrap();
-        let positions: Vec<Vec2> = level
-            .path
-            .iter()
-            .map(|pos| vec2(pos[0], pos[1]) * Vec2::splat(TILE_SIZE) + Vec2::splat(TILE_SIZE / 2.))
-            .collect();
-
-        let mut tiles = positions.iter();
-
-        let start = tiles.next().unwrap();
-        path.start_position = *start;
-        path.positions.push_back(PathNode::new(*start));
-
-        commands.spawn(
-            Tile::new(&asset_server)
-                .with_texture(asset_server.load("resources/hole.png"))
-                .with_position(start.extend(PATH_LAYER)),
+        focus_camera(&mut camera_transform, level);
+        build_level(
+            &mut commands,
+            &asset_server,
+            &mut occupancy,
+            level,
+            seed_override.seed(level),
         );
+        commands.insert_resource(RoundCounter::default());
+        commands.insert_resource(Lives(level.lives));
+        commands.insert_resource(Money(level.money));
+        commands.insert_resource(GameSpeed::default());
+        commands.insert_resource(SimTime::default());
 
-        let end = tiles.next_back().unwrap();
-        path.end_position = *end;
-        path.positions.push_front(PathNode::new(*end));
+        state.set(AppState::Level).unwrap();
+    }
+}
 
-        commands.spawn(
-            PathTile::new(&asset_server)
-                .with_texture(asset_server.load("resources/hole.png"))
-                .with_position(end.extend(PATH_LAYER)),
-        );
+/// Rebuilds the level in place when its file changes on disk.
+/// Turrets stay unless the new level puts a path, blocking decor or unbuildable
+/// terrain under them, or leaves them outside the map.
+/// A level that gets fixed while its errors are shown starts over from `Loading`.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn reload_level(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut events: EventReader<AssetEvent<Level>>,
+    level: Res<LevelHandle>,
+    levels: Res<Assets<Level>>,
+    enemy_table: Res<EnemyTableHandle>,
+    enemy_tables: Res<Assets<EnemyTable>>,
+    mut state: ResMut<State<AppState>>,
+    mut camera_q: Query<&mut Transform, With<Camera>>,
+    level_q: Query<Entity, Or<(With<LevelEntity>, With<Enemy>, With<Projectile>)>>,
+    turret_q: Query<(Entity, &Transform, &Turret, &Children), (Without<Selected>, Without<Camera>)>,
+    mut gun_q: Query<&mut GunState>,
+    mut occupancy: ResMut<Occupancy>,
+    mut round_counter: ResMut<RoundCounter>,
+    seed_override: Res<SeedOverride>,
+) {
+    let mut changed = false;
+    for event in events.iter() {
+        match (event, state.current()) {
+            (AssetEvent::Modified { handle }, AppState::Level | AppState::Paused)
+            | (
+                AssetEvent::Created { handle } | AssetEvent::Modified { handle },
+                AppState::LevelError,
+            ) => changed |= handle == &level.0,
+            _ => {}
+        }
+    }
 
-        // Move camera to middle of the map based on strong assumptions
-        camera_transform.translation += Vec2::from_array(level.center_pos).extend(0.) * TILE_SIZE;
-        let mut rng = thread_rng();
-
-        for x in -MAP_SIZE..MAP_SIZE {
-            for y in -MAP_SIZE..MAP_SIZE {
-                commands.spawn(Tile::new(&asset_server).with_position(Vec3 {
-                    x: x as f32 * TILE_SIZE,
-                    y: y as f32 * TILE_SIZE,
-                    z: BACKGROUND_LAYER,
-                }));
-
-                let r = rng.gen_range(0..50);
-                if r < 5 {
-                    commands.spawn(
-                        Tile::new(&asset_server)
-                            .with_texture(asset_server.load("resources/grass.png"))
-                            .with_position(Vec3 {
-                                x: x as f32 * TILE_SIZE - TILE_SIZE / 2.,
-                                y: y as f32 * TILE_SIZE - TILE_SIZE / 2.,
-                                z: (BACKGROUND_LAYER + PATH_LAYER) / 2., // TODO name layer
-                            }),
-                    );
-                } else if r < 7 {
-                    commands.spawn(
-                        Tile::new(&asset_server)
-                            .with_texture(asset_server.load("resources/stone.png"))
-                            .with_position(Vec3 {
-                                x: x as f32 * TILE_SIZE - TILE_SIZE / 2.,
-                                y: y as f32 * TILE_SIZE - TILE_SIZE / 2.,
-                                z: (BACKGROUND_LAYER + PATH_LAYER) / 2.,
-                            }),
-                    );
-                }
-            }
+    let Some(level) = levels.get(&level.0).filter(|_| changed) else {
+        return;
+    };
+    let Some(enemies) = enemy_tables.get(&enemy_table.0) else {
+        return;
+    };
+
+    if let Err(errors) = level.validate(enemies) {
+        report_level_errors(&mut commands, errors);
+        if state.current() != &AppState::LevelError {
+            // Also drops a paused level from the state stack
+            state.overwrite_replace(AppState::LevelError).unwrap();
         }
+        return;
+    }
+
+    // Lives, money and the clocks are only set up when a level starts
+    // Backspace may already be leaving the error screen this frame
+    if state.current() == &AppState::LevelError {
+        let _ = state.set(AppState::Loading);
+        return;
+    }
+
+    for entity in &level_q {
+        commands.entity(entity).despawn();
+    }
 
-        for pos in tiles {
-            path.positions.push_back(PathNode::new(*pos));
-            commands.spawn(PathTile::new(&asset_server).with_position(pos.extend(PATH_LAYER)));
+    focus_camera(&mut camera_q.single_mut(), level);
+    build_level(
+        &mut commands,
+        &asset_server,
+        &mut occupancy,
+        level,
+        seed_override.seed(level),
+    );
+
+    let terrain = level.terrain();
+    for (turret_ent, turret_t, turret, children) in &turret_q {
+        let footprint = || footprint_cells(turret_t.translation.truncate(), turret.size());
+        if !occupancy.holds(turret_ent, footprint()) {
+            occupancy.remove_turret(turret_ent, footprint());
+            commands.entity(turret_ent).despawn_recursive();
+            continue;
         }
+        let mut guns = gun_q.iter_many_mut(children);
+        while let Some(mut gun_state) = guns.fetch_next() {
+            gun_state.range_bonus = terrain.range_bonus(footprint());
+        }
+    }
+    // Replay the round that was interrupted
+    round_counter.0 = round_counter.0.saturating_sub(1);
 
-        for (image_path, pos, blocks) in level.decor.into_iter() {
-            let decor_asset = asset_server.load::<Image, _>(image_path);
-            let decor_pos = vec2(pos[0], pos[1]) * TILE_SIZE + Vec2::splat(TILE_SIZE / 2.);
-            let tile =
-                Tile::new_decor(decor_asset).with_position(decor_pos.extend(PATH_LAYER + 0.1));
-            if blocks {
-                commands.spawn((tile, Collider(ColliderType::Decor)));
-            } else {
-                commands.spawn(tile);
+    // Saving in another window pauses the game, show the change right away.
+    // P may already have resumed it this frame.
+    if state.current() == &AppState::Paused {
+        let _ = state.pop();
+    }
+}
+
+fn report_level_errors(commands: &mut Commands, errors: Vec<LevelError>) {
+    for error in &errors {
+        error!("level: {error}");
+    }
+    commands.insert_resource(LevelErrors(errors));
+}
+
+pub fn focus_camera(camera_transform: &mut Transform, level: &Level) {
+    camera_transform.translation =
+        (level.center() * TILE_SIZE).extend(camera_transform.translation.z);
+}
+
+// Spawns everything the level file describes, tagged with `LevelEntity`
+pub fn build_level(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    occupancy: &mut Occupancy,
+    level: &Level,
+    seed: u64,
+) {
+    info!("building level with seed {seed}");
+    let mut rng = GameRng::new(seed);
+
+    let terrain = level.terrain();
+    occupancy.set_level(level, &terrain);
+
+    let paths = match level.maze {
+        Some(maze) => {
+            let (spawn, exit) = (maze.spawn_cell(), maze.exit_cell());
+            let route =
+                find_path(spawn, exit, |cell| occupancy.is_walkable(cell)).unwrap_or_else(|| {
+                    // Turrets kept through a reload close the maze, the ones in the way have to go
+                    let route = level.maze_route().unwrap_or(vec![spawn, exit]);
+                    occupancy.add_path(route.iter().copied());
+                    route
+                });
+
+            for cell in [spawn, exit] {
+                commands.spawn((
+                    Tile::new(asset_server)
+                        .with_texture(asset_server.load("resources/hole.png"))
+                        .with_position(GridPos(cell).center().extend(PATH_LAYER)),
+                    LevelEntity,
+                ));
             }
+            commands.insert_resource(maze);
+            vec![Path::from_cells(&route)]
         }
+        None => {
+            commands.remove_resource::<Maze>();
+            level
+                .paths
+                .iter()
+                .enumerate()
+                .map(|(idx, tiles)| {
+                    let forks: Vec<&Fork> = level.forks.iter().filter(|f| f.path == idx).collect();
+                    let path = Path::new(tiles, &forks);
+                    spawn_path(commands, asset_server, &path, &mut rng.cosmetic);
+                    path
+                })
+                .collect()
+        }
+    };
+    commands.insert_resource(Paths(paths));
 
-        commands.insert_resource(SpawnTimer::default());
-        commands.insert_resource(RoundCounter::default());
-        commands.insert_resource(Round(vec![]));
+    let bounds = level.bounds();
+    commands.insert_resource(bounds);
 
-        state.set(AppState::Level).unwrap();
+    for x in bounds.min.x..bounds.max.x {
+        for y in bounds.min.y..bounds.max.y {
+            let terrain_type = terrain.get(IVec2::new(x, y)).unwrap();
+            commands.spawn((
+                Tile::new(asset_server)
+                    .with_texture(asset_server.load(terrain_type.texture.as_str()))
+                    .with_color(terrain_type.color())
+                    .with_position(GridPos::new(x, y).center().extend(BACKGROUND_LAYER)),
+                LevelEntity,
+            ));
+
+            let r = rng.cosmetic.gen_range(0..50);
+            if r < 5 {
+                commands.spawn((
+                    Tile::new(asset_server)
+                        .with_texture(asset_server.load("resources/grass.png"))
+                        .with_position(
+                            GridPos::new(x, y)
+                                .corner()
+                                .extend((BACKGROUND_LAYER + PATH_LAYER) / 2.), // TODO name layer
+                        ),
+                    LevelEntity,
+                ));
+            } else if r < 7 {
+                commands.spawn((
+                    Tile::new(asset_server)
+                        .with_texture(asset_server.load("resources/stone.png"))
+                        .with_position(
+                            GridPos::new(x, y)
+                                .corner()
+                                .extend((BACKGROUND_LAYER + PATH_LAYER) / 2.),
+                        ),
+                    LevelEntity,
+                ));
+            }
+        }
+    }
+
+    commands.insert_resource(terrain);
+
+    for (image_path, pos, blocks) in level.decor.iter() {
+        let decor_asset = asset_server.load::<Image, _>(image_path.as_str());
+        let decor_pos = GridPos::from_tiles(*pos).center();
+        let tile = Tile::new_decor(decor_asset).with_position(decor_pos.extend(PATH_LAYER + 0.1));
+        if *blocks {
+            commands.spawn((tile, Collider(ColliderType::Decor), LevelEntity));
+        } else {
+            commands.spawn((tile, LevelEntity));
+        }
     }
+
+    commands.insert_resource(RoundList {
+        waves: level.waves.clone(),
+        endless: level.endless,
+    });
+    commands.insert_resource(SpawnTimer::default());
+    commands.insert_resource(Round::default());
+    // Play goes on with the streams the level was built with
+    commands.insert_resource(rng);
 }
 
+// Spawns the tiles of one path with holes at its start and end
+fn spawn_path(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    path: &Path,
+    rng: &mut impl Rng,
+) {
+    for (idx, node) in path.positions.iter().enumerate() {
+        let position = node.position.extend(PATH_LAYER);
+        if idx == 0 {
+            commands.spawn((
+                Tile::new(asset_server)
+                    .with_texture(asset_server.load("resources/hole.png"))
+                    .with_position(position),
+                LevelEntity,
+            ));
+        } else if node.next.is_empty() {
+            commands.spawn((
+                PathTile::new(asset_server, rng)
+                    .with_texture(asset_server.load("resources/hole.png"))
+                    .with_position(position),
+                LevelEntity,
+            ));
+        } else {
+            commands.spawn((
+                PathTile::new(asset_server, rng).with_position(position),
+                LevelEntity,
+            ));
+        }
+    }
+}
+
+pub fn show_level_errors(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    errors: Res<LevelErrors>,
+    text_q: Query<Entity, With<LevelErrorText>>,
+) {
+    if !errors.is_changed() {
+        return;
+    }
+
+    for entity in &text_q {
+        commands.entity(entity).despawn();
+    }
+    let font = asset_server.load("fonts/ComicMono.ttf");
+    commands.spawn(LevelErrorBundle::new(font, &errors.0));
+}
+
+#[allow(clippy::type_complexity)]
 pub fn move_cursor(
     mut query: Query<(&mut Transform, &mut GridCursor), (Without<Camera>, Without<Selected>)>,
     camera_q: Query<&Transform, With<Camera>>,
@@ -226,29 +636,14 @@ pub fn move_cursor(
     time: Res<Time>,
 ) {
     let window = windows.get_primary().unwrap();
-    let window_size = Vec2::new(window.width(), window.height());
     let camera_transform = camera_q.get_single().unwrap();
 
-    // TODO clean this ugly mess
-    if let Some(cursor_position) = window.cursor_position() {
-        let elapsed = time.elapsed().as_secs_f32();
+    if let Some(cursor_world) = cursor_world_position(window, camera_transform) {
+        // Real time, so the cursor feels the same at any game speed
+        let elapsed = time.raw_elapsed().as_secs_f32();
         let (mut cursor_transform, mut grid_cursor) = query.get_single_mut().unwrap();
 
-        let mut target_pos_grid =
-            cursor_position - (window_size / 2.) + camera_transform.translation.truncate();
-
-        // 1 round of magic
-        if grid_cursor.selection_size.x as i32 % 2 == 0 {
-            target_pos_grid -= Vec2::splat(TILE_SIZE / 2.);
-        }
-
-        target_pos_grid =
-            (target_pos_grid / TILE_SIZE).floor() * TILE_SIZE + Vec2::splat(TILE_SIZE / 2.);
-
-        // 2 rounds of magic
-        if grid_cursor.selection_size.x as i32 % 2 == 0 {
-            target_pos_grid += Vec2::splat(TILE_SIZE / 2.);
-        }
+        let target_pos_grid = snap_footprint(cursor_world, grid_cursor.selection_size.as_ivec2());
 
         let prev_pos = cursor_transform.translation.xy();
         let delta = target_pos_grid - prev_pos;
@@ -268,8 +663,6 @@ pub fn move_cursor(
 
         let newp = prev_pos + delta * ease(dt / EASE_TIME);
         cursor_transform.translation = newp.extend(CURSOR_LAYER);
-    } else {
-        // Window is not active => game should be paused
     }
 }
 
@@ -287,158 +680,257 @@ pub fn handle_cursor_visibility(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn handle_place(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     cursor_q: Query<(&Transform, &GridCursor), Without<Turret>>,
     child_q: Query<(&Transform, &Turret), With<Selected>>,
     buttons: Res<Input<MouseButton>>,
+    terrain: Res<Terrain>,
+    maze: Option<Res<Maze>>,
+    mut paths: ResMut<Paths>,
+    mut enemy_q: Query<(&Transform, &mut Enemy)>,
+    mut occupancy: ResMut<Occupancy>,
+    mut money: ResMut<Money>,
 ) {
-    let (cursor_transform, cursor) = cursor_q.get_single().unwrap();
+    let (_cursor_transform, cursor) = cursor_q.get_single().unwrap();
     if !cursor.can_place {
         return;
     }
 
     if let Ok((transform, placeable)) = child_q.get_single() {
         if buttons.just_pressed(MouseButton::Left) {
+            let footprint = || footprint_cells(cursor.last_target_pos, placeable.size());
+            // `can_place` may have been worked out for an older cursor position
+            if !occupancy.is_free(footprint()) {
+                return;
+            }
+            if let Some(maze) = &maze {
+                let enemies = enemy_q.iter().map(|(enemy_t, _)| enemy_t);
+                if closes_maze(maze, &occupancy, footprint().collect(), enemies) {
+                    return;
+                }
+            }
+            // An upgrade earlier this frame may have spent the money the cursor counted on
+            let Some(money_left) = money.0.checked_sub(placeable.cost()) else {
+                return;
+            };
+
             let target_transform = Transform::from_xyz(
                 cursor.last_target_pos.x,
                 cursor.last_target_pos.y,
                 CURSOR_LAYER,
             )
-            .with_scale(transform.scale * TILE_SIZE);
+            // z stays 1 so the gun child keeps its layer offset
+            .with_scale((transform.scale.truncate() * TILE_SIZE).extend(1.));
 
             let turret =
                 TurretBundle::new(*placeable, &asset_server).with_transform(target_transform);
 
-            let gun = GunBundle::new(turret.turret.gun(), &asset_server).with_transform(
-                target_transform.with_translation(target_transform.translation + vec3(0., 0., 1.)),
-            );
-
-            commands.spawn(turret);
-            commands.spawn(gun);
+            // Positioned relative to the turret
+            let mut gun = GunBundle::new(turret.turret.gun(), &asset_server)
+                .with_transform(Transform::from_xyz(0., 0., 1.));
+            gun.gun_state.range_bonus = terrain.range_bonus(footprint());
+
+            money.0 = money_left;
+            let entity = commands
+                .spawn(turret)
+                .with_children(|parent| {
+                    parent.spawn(gun);
+                })
+                .id();
+            occupancy.add_turret(entity, footprint());
+
+            if let Some(maze) = &maze {
+                reroute_maze(maze, &occupancy, &mut paths, &mut enemy_q);
+            }
         }
     }
 }
 
-pub fn handle_collisions(
-    mut cursor_q: Query<(&Transform, &mut GridCursor)>,
-    collider_q: Query<
-        (&Transform, &Collider),
-        (Without<GridCursor>, Without<Turret>, Without<Projectile>),
-    >,
-    turret_q: Query<(&Transform, &Turret), (With<Collider>, Without<Selected>)>,
-) {
-    let (_cursor_transform, mut cursor) = cursor_q.single_mut();
+// Whether building on `footprint` leaves the spawn or any enemy without a way out of the maze
+fn closes_maze<'a>(
+    maze: &Maze,
+    occupancy: &Occupancy,
+    footprint: HashSet<IVec2>,
+    enemies: impl Iterator<Item = &'a Transform>,
+) -> bool {
+    let walkable = |cell| occupancy.is_walkable(cell) && !footprint.contains(&cell);
+    let starts: HashSet<IVec2> = enemies
+        .map(|enemy_t| GridPos::from_world(enemy_t.translation.truncate()).0)
+        .chain([maze.spawn_cell()])
+        .collect();
+
+    starts
+        .into_iter()
+        .any(|start| !walkable(start) || find_path(start, maze.exit_cell(), walkable).is_none())
+}
 
-    let mut colliding = false;
+// Sends every enemy in the maze along its shortest way out again once the turrets change
+fn reroute_maze(
+    maze: &Maze,
+    occupancy: &Occupancy,
+    paths: &mut Paths,
+    enemy_q: &mut Query<(&Transform, &mut Enemy)>,
+) {
+    let exit = maze.exit_cell();
+    let route = |from: IVec2| {
+        let cells = find_path(from, exit, |cell| occupancy.is_walkable(cell))
+            .unwrap_or_else(|| vec![from, exit]);
+        Path::from_cells(&cells)
+    };
+
+    // Path 0 is where new enemies spawn, enemies in the same cell share theirs
+    paths.0 = vec![route(maze.spawn_cell())];
+    let mut starts: HashMap<IVec2, usize> = HashMap::new();
+    for (enemy_t, mut enemy) in enemy_q.iter_mut() {
+        let cell = GridPos::from_world(enemy_t.translation.truncate()).0;
+        enemy.path = *starts.entry(cell).or_insert_with(|| {
+            paths.0.push(route(cell));
+            paths.0.len() - 1
+        });
+        // Back to the middle of the cell first, cutting the corner could clip a turret
+        enemy.idx = 0;
+        enemy.next = Some(0);
+    }
+}
 
-    for (collision_transform, _collider) in &collider_q {
-        let collision = collide(
-            cursor.last_target_pos.extend(0.),
-            Vec2::splat(TILE_SIZE * cursor.selection_size.x),
-            collision_transform.translation,
-            collision_transform.scale.xy(),
-        );
+pub fn handle_collisions(
+    mut cursor_q: Query<&mut GridCursor>,
+    selected_q: Query<&Turret, With<Selected>>,
+    enemy_q: Query<&Transform, With<Enemy>>,
+    maze: Option<Res<Maze>>,
+    occupancy: Res<Occupancy>,
+    money: Res<Money>,
+) {
+    let mut cursor = cursor_q.single_mut();
 
-        if collision.is_some() {
-            colliding = true;
-            break;
-        }
-    }
+    // Also covers the map edges and unbuildable terrain
+    let target = (cursor.last_target_pos, cursor.selection_size);
+    let footprint = || footprint_cells(target.0, target.1.as_ivec2());
+    let mut colliding = !occupancy.is_free(footprint());
 
-    for (collision_transform, _turret) in &turret_q {
-        let collision = collide(
-            cursor.last_target_pos.extend(0.),
-            Vec2::splat(TILE_SIZE * cursor.selection_size.x),
-            collision_transform.translation,
-            collision_transform.scale.xy(),
-        );
+    if let Ok(selected) = selected_q.get_single() {
+        colliding |= money.0 < selected.cost();
 
-        if collision.is_some() {
-            colliding = true;
-            break;
+        if let Some(maze) = maze.as_ref().filter(|_| !colliding) {
+            let cached = cursor.closes_maze.filter(|(key, _)| *key == target);
+            let closes = match cached {
+                Some((_, closes)) if !occupancy.is_changed() => closes,
+                _ => closes_maze(maze, &occupancy, footprint().collect(), enemy_q.iter()),
+            };
+            cursor.closes_maze = Some((target, closes));
+            colliding |= closes;
         }
     }
 
     cursor.can_place = !colliding;
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn handle_sell(
     mut commands: Commands,
-    cursor_q: Query<&GridCursor>,
-    collider_q: Query<(&Transform, Entity, &Collider, &Turret), Without<GridCursor>>,
+    turret_q: Query<(&Transform, &Turret, &Children)>,
+    gun_q: Query<&GunState>,
+    camera_q: Query<&Transform, With<Camera>>,
+    windows: Res<Windows>,
     buttons: Res<Input<MouseButton>>,
+    maze: Option<Res<Maze>>,
+    mut paths: ResMut<Paths>,
+    mut enemy_q: Query<(&Transform, &mut Enemy)>,
+    mut occupancy: ResMut<Occupancy>,
+    mut money: ResMut<Money>,
 ) {
-    let grid_cursor = cursor_q.single();
-
-    if buttons.just_pressed(MouseButton::Right) {
-        for (collider_transform, entity, _c, _p) in &collider_q {
-            if let Some(Collision::Inside) = collide(
-                grid_cursor.last_target_pos.extend(0.),
-                vec2(0., 0.),
-                collider_transform.translation,
-                collider_transform.scale.truncate(),
-            ) {
-                commands.entity(entity).despawn();
-            }
-        }
+    if !buttons.just_pressed(MouseButton::Right) {
+        return;
+    }
+
+    let window = windows.get_primary().unwrap();
+    let Some(entity) =
+        cursor_cell(window, camera_q.single()).and_then(|cell| occupancy.turret_at(cell.0))
+    else {
+        return;
+    };
+    let Ok((turret_t, turret, children)) = turret_q.get(entity) else {
+        return;
+    };
+
+    let tier = gun_q.iter_many(children).map(|gun| gun.tier).max();
+    money.0 += turret.refund(tier.unwrap_or_default());
+    occupancy.remove_turret(
+        entity,
+        footprint_cells(turret_t.translation.truncate(), turret.size()),
+    );
+    commands.entity(entity).despawn_recursive();
+
+    // The way out may be shorter now
+    if let Some(maze) = &maze {
+        reroute_maze(maze, &occupancy, &mut paths, &mut enemy_q);
+    }
+}
+
+/// Marks the level won once its last wave is cleared and, unless it is endless,
+/// sends the player back to the level select
+#[allow(clippy::too_many_arguments)]
+pub fn check_level_complete(
+    enemy_q: Query<(), With<Enemy>>,
+    round_counter: Res<RoundCounter>,
+    roundlist: Res<RoundList>,
+    round: Res<Round>,
+    current: Res<CurrentLevel>,
+    lives: Res<Lives>,
+    mut progress: ResMut<CampaignProgress>,
+    mut state: ResMut<State<AppState>>,
+) {
+    // Endless levels are won by clearing the waves they were written with
+    if round_counter.0 == 0
+        || round_counter.0 < roundlist.waves.len()
+        || lives.0 == 0
+        || !enemy_q.is_empty()
+        || !round.0.is_empty()
+    {
+        return;
+    }
+
+    if progress.completed.insert(current.0.name.clone()) {
+        info!("level {} complete", current.0.name);
+        progress.save();
+    }
+    // Endless rounds go on for as long as the player lasts
+    if roundlist.endless.is_none() {
+        // The fixed step can run again before the state actually changes
+        state.overwrite_set(AppState::LevelSelect).unwrap();
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn game_tick(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    mut path: ResMut<Path>, //
-    mut enemy_q: Query<(&mut Transform, &mut Enemy)>,
+    mut paths: ResMut<Paths>,
+    mut enemy_q: Query<(Entity, &mut Transform, &mut Enemy)>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    enemy_table: Res<EnemyTableHandle>,
+    enemy_tables: Res<Assets<EnemyTable>>,
     mut round_counter: ResMut<RoundCounter>,
     roundlist: Res<RoundList>,
     mut round: ResMut<Round>,
-    time: Res<Time>,
+    sim_time: Res<SimTime>,
     mut spawn_timer: ResMut<SpawnTimer>,
+    mut lives: ResMut<Lives>,
+    mut rng: ResMut<GameRng>,
+    mut state: ResMut<State<AppState>>,
 ) {
-    spawn_timer.0.tick(time.delta());
-    if spawn_timer.0.finished() {
-        if let Some(kind) = round.0.pop() {
-            if kind == EnemyKind::Pepper {
-                let animation_indices = AnimationIndices { first: 0, last: 3 };
-                commands.spawn((
-                    EnemyBundle::new(
-                        Enemy::new(EnemyKind::Pepper, 0),
-                        &asset_server,
-                        &mut texture_atlases,
-                    )
-                    .with_position(path.start_position.extend(0.)),
-                    animation_indices,
-                    AnimationTimer(Timer::from_seconds(0.1, TimerMode::Repeating)),
-                    Collider(ColliderType::Enemy),
-                ));
-            }
-            commands.spawn((
-                EnemyBundle::new(Enemy::new(kind, 0), &asset_server, &mut texture_atlases)
-                    .with_position(path.start_position.extend(0.)),
-                Collider(ColliderType::Enemy),
-            ));
-        }
-    }
-
+    // Checked before spawning so an enemy spawned this tick still counts as alive
     if enemy_q.iter().count() < 1 && round.0.is_empty() {
-        round_counter.next();
-
-        let mut enemies: Vec<EnemyKind> = vec![];
-        if let Some(enemylist) = (*roundlist).0.get(round_counter.0 - 1) {
-            for (amount, kind) in enemylist {
-                for _ in 0..*amount {
-                    enemies.push(kind.clone());
-                }
-            }
-        } else {
-            for _ in 0..round_counter.0 {
-                enemies.push(EnemyKind::Pepper);
+        if let Some(groups) = roundlist.round(round_counter.0 + 1) {
+            round_counter.next();
+            round.0 = groups.into_iter().filter(|group| group.count > 0).collect();
+            if let Some(group) = round.0.front() {
+                spawn_timer.0 = Timer::from_seconds(group.delay, TimerMode::Once);
             }
         }
-        commands.insert_resource(Round(enemies));
 
         // commands.spawn((
         //     EnemyBundle::new(
@@ -464,9 +956,45 @@ pub fn game_tick(
         // ));
     }
 
-    for (mut enemy_transform, mut enemy) in enemy_q.iter_mut() {
-        if let Some(next_tile) = path.positions.get_mut(enemy.idx + 1) {
-            let next_pos = next_tile.position;
+    spawn_timer.0.tick(sim_time.delta());
+    if spawn_timer.0.finished() {
+        if let Some(group) = round.0.front_mut() {
+            let start = paths.0[group.path].start_position;
+            let enemy_type = enemy_tables
+                .get(&enemy_table.0)
+                .and_then(|table| table.get(&group.kind));
+            if let Some(enemy_type) = enemy_type {
+                spawn_enemy(
+                    &mut commands,
+                    &asset_server,
+                    &mut texture_atlases,
+                    Enemy::new(group.kind.clone(), enemy_type, group.path, 0),
+                    enemy_type,
+                    start.extend(0.),
+                );
+            }
+
+            group.count -= 1;
+            let next_spawn = if group.count > 0 {
+                Some(group.interval)
+            } else {
+                round.0.pop_front();
+                round.0.front().map(|group| group.delay)
+            };
+            if let Some(seconds) = next_spawn {
+                spawn_timer.0 = Timer::from_seconds(seconds, TimerMode::Once);
+            }
+        }
+    }
+
+    for (enemy_ent, mut enemy_transform, mut enemy) in enemy_q.iter_mut() {
+        let path = &mut paths.0[enemy.path];
+        if enemy.next.is_none() {
+            enemy.next = path.choose_next(enemy.idx, &mut rng.gameplay);
+        }
+
+        if let Some(next_idx) = enemy.next {
+            let next_pos = path.positions[next_idx].position;
             let prev_pos = enemy_transform.translation.xy();
             let newp = (next_pos - prev_pos).normalize_or_zero() * enemy.speed() + prev_pos;
             enemy_transform.translation = newp.extend(ENEMY_LAYER);
@@ -474,12 +1002,249 @@ pub fn game_tick(
             if (enemy_transform.translation)
                 .abs_diff_eq(next_pos.extend(ENEMY_LAYER), TILE_SIZE / 20.)
             {
-                enemy.idx += 1;
+                enemy.idx = next_idx;
+                enemy.next = path.choose_next(next_idx, &mut rng.gameplay);
             }
+        } else {
+            // Made it into the end hole
+            commands.entity(enemy_ent).despawn();
+
+            let damage = enemy_tables
+                .get(&enemy_table.0)
+                .and_then(|table| table.get(&enemy.kind))
+                .map_or(1, |enemy_type| enemy_type.damage);
+            if lives.0 > 0 && damage > 0 {
+                lives.0 = lives.0.saturating_sub(damage);
+                if lives.0 == 0 {
+                    // Wins over a pause queued in the same frame
+                    state.overwrite_set(AppState::GameOver).unwrap();
+                }
+            }
+        }
+    }
+}
+
+// Spawns an enemy with its animation, if its kind has one
+fn spawn_enemy(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
+    enemy: Enemy,
+    enemy_type: &EnemyType,
+    position: Vec3,
+) {
+    let mut entity = commands.spawn((
+        EnemyBundle::new(enemy, enemy_type, asset_server, texture_atlases).with_position(position),
+        Collider(ColliderType::Enemy),
+    ));
+    if let Some(animation) = enemy_type.animation {
+        entity.insert((
+            AnimationIndices {
+                first: animation.first,
+                last: animation.last,
+            },
+            AnimationTimer(Timer::from_seconds(
+                animation.frame_time,
+                TimerMode::Repeating,
+            )),
+        ));
+    }
+}
+
+/// Cycles the targeting mode of the tower under the mouse
+pub fn handle_target_mode(
+    turret_q: Query<&Children, With<Turret>>,
+    mut gun_q: Query<&mut GunState>,
+    camera_q: Query<&Transform, With<Camera>>,
+    windows: Res<Windows>,
+    keys: Res<Input<KeyCode>>,
+    occupancy: Res<Occupancy>,
+) {
+    if !keys.just_pressed(KeyCode::T) {
+        return;
+    }
+
+    let window = windows.get_primary().unwrap();
+    let Some(cell) = cursor_cell(window, camera_q.single()) else {
+        return;
+    };
+
+    let Some(children) = occupancy
+        .turret_at(cell.0)
+        .and_then(|entity| turret_q.get(entity).ok())
+    else {
+        return;
+    };
+    let mut guns = gun_q.iter_many_mut(children);
+    while let Some(mut gun_state) = guns.fetch_next() {
+        gun_state.target = gun_state.target.next();
+    }
+}
+
+/// Buys the next upgrade for the tower under the mouse
+#[allow(clippy::too_many_arguments)]
+pub fn handle_upgrade(
+    asset_server: Res<AssetServer>,
+    turret_q: Query<&Children, With<Turret>>,
+    mut gun_q: Query<(&Gun, &mut GunState, &mut Handle<Image>)>,
+    camera_q: Query<&Transform, With<Camera>>,
+    windows: Res<Windows>,
+    keys: Res<Input<KeyCode>>,
+    occupancy: Res<Occupancy>,
+    mut money: ResMut<Money>,
+) {
+    if !keys.just_pressed(KeyCode::U) {
+        return;
+    }
+
+    let window = windows.get_primary().unwrap();
+    let Some(cell) = cursor_cell(window, camera_q.single()) else {
+        return;
+    };
+
+    let Some(children) = occupancy
+        .turret_at(cell.0)
+        .and_then(|entity| turret_q.get(entity).ok())
+    else {
+        return;
+    };
+    let mut guns = gun_q.iter_many_mut(children);
+    while let Some((gun, mut gun_state, mut texture)) = guns.fetch_next() {
+        let Some(upgrade) = gun.upgrade(gun_state.tier + 1) else {
+            continue;
+        };
+        if money.0 < upgrade.cost {
+            continue;
+        }
+
+        money.0 -= upgrade.cost;
+        gun_state.tier += 1;
+        *texture = gun.sprite(gun_state.tier, &asset_server);
+    }
+}
+
+/// Shows the stats of the tower under the mouse
+pub fn update_inspect(
+    turret_q: Query<(&Turret, &Children)>,
+    gun_q: Query<(&Gun, &GunState)>,
+    mut text_q: Query<&mut Text, With<InspectText>>,
+    camera_q: Query<&Transform, With<Camera>>,
+    windows: Res<Windows>,
+    occupancy: Res<Occupancy>,
+) {
+    let Ok(mut text) = text_q.get_single_mut() else {
+        return;
+    };
+
+    let window = windows.get_primary().unwrap();
+    let hovered = cursor_cell(window, camera_q.single())
+        .and_then(|cell| occupancy.turret_at(cell.0))
+        .and_then(|entity| turret_q.get(entity).ok())
+        .and_then(|(turret, children)| {
+            gun_q
+                .iter_many(children)
+                .next()
+                .map(|(gun, gun_state)| (turret, gun, gun_state))
+        });
+
+    text.sections[0].value = match hovered {
+        Some((turret, gun, gun_state)) => {
+            let upgrade = match gun.upgrade(gun_state.tier + 1) {
+                Some(upgrade) => format!("U: upgrade ({})", upgrade.cost),
+                None => "Fully upgraded".to_string(),
+            };
+            format!(
+                "{turret:?} tier {}\n\
+                 Range: {:.1}\n\
+                 Rate: {:.1}/s\n\
+                 Projectile: {:?}\n\
+                 T: target {:?}\n\
+                 {upgrade}\n\
+                 Right click: sell ({})",
+                gun_state.tier + 1,
+                gun_state.range(gun) / TILE_SIZE,
+                gun.rate(gun_state.tier),
+                gun.projectile(gun_state.tier),
+                gun_state.target,
+                turret.refund(gun_state.tier),
+            )
+        }
+        None => String::new(),
+    };
+}
+
+/// Shows the range of the hovered tower, or of the turret being placed,
+/// and lights up the enemies inside it
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn update_range_preview(
+    state: Res<State<AppState>>,
+    mut preview_q: Query<(&mut Transform, &mut Visibility), With<RangePreview>>,
+    cursor_q: Query<(&Transform, &GridCursor), Without<RangePreview>>,
+    selected_q: Query<&Turret, With<Selected>>,
+    turret_q: Query<(&Transform, &Children), (With<Turret>, Without<RangePreview>)>,
+    gun_q: Query<(&Gun, &GunState)>,
+    mut enemy_q: Query<(&Transform, &mut TextureAtlasSprite), (With<Enemy>, Without<RangePreview>)>,
+    camera_q: Query<&Transform, (With<Camera>, Without<RangePreview>)>,
+    windows: Res<Windows>,
+    terrain: Res<Terrain>,
+    occupancy: Res<Occupancy>,
+) {
+    let (mut preview_t, mut visibility) = preview_q.single_mut();
+    let window = windows.get_primary().unwrap();
+
+    let hovered = |cell: GridPos| {
+        let (turret_t, children) = turret_q.get(occupancy.turret_at(cell.0)?).ok()?;
+        let (gun, gun_state) = gun_q.iter_many(children).next()?;
+        Some((turret_t.translation.truncate(), gun_state.range(gun)))
+    };
+    let placing = || {
+        let (cursor_t, grid_cursor) = cursor_q.get_single().ok()?;
+        let turret = selected_q.get_single().ok()?;
+        let bonus =
+            terrain.range_bonus(footprint_cells(grid_cursor.last_target_pos, turret.size()));
+        Some((
+            cursor_t.translation.truncate(),
+            turret.gun().range(0) + bonus * TILE_SIZE,
+        ))
+    };
+
+    let shown = cursor_cell(window, camera_q.single())
+        .filter(|_| state.current() == &AppState::Level)
+        .and_then(|cell| hovered(cell).or_else(placing));
+
+    visibility.is_visible = shown.is_some();
+    if let Some((center, range)) = shown {
+        preview_t.translation = center.extend(RANGE_LAYER);
+        preview_t.scale = Vec3::new(range, range, 1.);
+    }
+
+    for (enemy_t, mut sprite) in &mut enemy_q {
+        let in_range = shown.is_some_and(|(center, range)| {
+            enemy_t.translation.truncate().distance(center) <= range
+        });
+        sprite.color = if in_range {
+            IN_RANGE_COLOR
+        } else {
+            Color::WHITE
         };
     }
 }
 
+pub fn cursor_world_position(window: &Window, camera_transform: &Transform) -> Option<Vec2> {
+    let window_size = Vec2::new(window.width(), window.height());
+    let cursor_position = window.cursor_position()?;
+    Some(window_to_world(
+        cursor_position,
+        window_size,
+        camera_transform,
+    ))
+}
+
+/// Grid cell under the mouse, `None` while it is outside the window
+pub fn cursor_cell(window: &Window, camera_transform: &Transform) -> Option<GridPos> {
+    cursor_world_position(window, camera_transform).map(GridPos::from_world)
+}
+
 pub fn handle_shop(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
@@ -517,50 +1282,88 @@ pub fn handle_shop(
     }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn handle_gunners(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-    mut gun_q: Query<(&mut Transform, &Gun, &mut GunState), Without<Selected>>,
-    enemies: Query<(&Transform, &Enemy), Without<Gun>>,
-    time: Res<Time>,
+    mut gun_q: Query<(&mut Transform, &Parent, &Gun, &mut GunState), Without<Selected>>,
+    turret_q: Query<&Transform, (With<Turret>, Without<Gun>)>,
+    enemies: Query<(&Transform, &Enemy), (Without<Gun>, Without<Turret>)>,
+    paths: Res<Paths>,
+    sim_time: Res<SimTime>,
 ) {
-    for (mut gun_t, gun, mut gun_state) in gun_q.iter_mut() {
-        if let Some(nearest_enemy) = enemies.iter().min_by(|(enemy_t_a, _), (enemy_t_b, _)| {
-            gun_t
-                .translation
-                .distance(enemy_t_a.translation)
-                .partial_cmp(&gun_t.translation.distance(enemy_t_b.translation))
-                .unwrap()
-        }) {
-            let delta = nearest_enemy.0.translation - gun_t.translation;
-            if delta.length() > gun.range() {
-                continue;
+    for (mut gun_t, parent, gun, mut gun_state) in gun_q.iter_mut() {
+        // The gun sits in the middle of its turret
+        let Ok(turret_t) = turret_q.get(parent.get()) else {
+            continue;
+        };
+        let gun_pos = turret_t.translation;
+
+        let range = gun_state.range(gun);
+        let in_range = enemies.iter().filter(|(enemy_t, _)| {
+            enemy_t.translation.truncate().distance(gun_pos.truncate()) <= range
+        });
+
+        // Lowest score gets shot
+        let score = |(enemy_t, enemy): &(&Transform, &Enemy)| {
+            let distance_left =
+                || enemy.distance_left(&paths.0[enemy.path], enemy_t.translation.truncate());
+            match gun_state.target {
+                TargetMode::First => distance_left(),
+                TargetMode::Last => -distance_left(),
+                TargetMode::Strongest => -enemy.health as f32,
+                TargetMode::Weakest => enemy.health as f32,
+                TargetMode::Nearest => gun_pos.distance(enemy_t.translation),
             }
+        };
+
+        if let Some(target) = in_range.min_by(|a, b| score(a).total_cmp(&score(b))) {
+            let delta = target.0.translation - gun_pos;
             let angle = delta.y.atan2(delta.x);
             gun_t.rotation = Quat::from_euler(EulerRot::XYZ, 0., 0., angle - PI / 2.);
-            if gun_state.last_shot + Duration::from_secs_f32(1. / gun.rate()) < time.elapsed() {
-                commands.spawn((
-                    ProjectileBundle::new(
-                        Projectile::new(ProjectileType::ChefsKnife),
-                        &asset_server,
-                        &mut texture_atlases,
-                    )
-                    .with_transform(
-                        Transform::from_translation(
-                            gun_t.translation.truncate().extend(PROJECTILE_LAYER),
-                        )
-                        .with_rotation(
-                            gun_t.rotation * Quat::from_euler(EulerRot::XYZ, 0., 0., -PI / 2.),
+            let reload = Duration::from_secs_f32(1. / gun.rate(gun_state.tier));
+            if gun_state.last_shot + reload < sim_time.elapsed {
+                let ty = gun.projectile(gun_state.tier);
+                let burst = gun.burst();
+                for i in 0..burst {
+                    // Fan the burst out evenly, a single projectile flies straight
+                    let offset = if burst > 1 {
+                        gun.spread() * (i as f32 / (burst - 1) as f32 - 0.5)
+                    } else {
+                        0.
+                    };
+
+                    let mut projectile = commands.spawn((
+                        ProjectileBundle::new(
+                            Projectile::new(ty, gun.damage(), gun.pierce()),
+                            &asset_server,
+                            &mut texture_atlases,
                         )
-                        .with_scale(Vec2::splat(TILE_SIZE / SPRITE_SIZE / 2.).extend(0.)),
-                    ),
-                    Collider(ColliderType::Projectile),
-                    AnimationIndices { first: 0, last: 2 },
-                    AnimationTimer(Timer::from_seconds(0.6, TimerMode::Repeating)),
-                ));
+                        .with_transform(
+                            Transform::from_translation(
+                                gun_pos.truncate().extend(PROJECTILE_LAYER),
+                            )
+                            .with_rotation(
+                                gun_t.rotation
+                                    * Quat::from_euler(EulerRot::XYZ, 0., 0., -PI / 2. + offset),
+                            )
+                            .with_scale(Vec2::splat(TILE_SIZE / SPRITE_SIZE / 2.).extend(0.)),
+                        ),
+                        Collider(ColliderType::Projectile),
+                    ));
+                    if ty.frames() > 1 {
+                        projectile.insert((
+                            AnimationIndices {
+                                first: 0,
+                                last: ty.frames() - 1,
+                            },
+                            AnimationTimer(Timer::from_seconds(0.6, TimerMode::Repeating)),
+                        ));
+                    }
+                }
 
-                gun_state.last_shot = time.elapsed();
+                gun_state.last_shot = sim_time.elapsed;
             }
         }
     }
@@ -569,13 +1372,12 @@ pub fn handle_gunners(
 pub fn handle_projectiles(
     mut commands: Commands,
     mut projectile_q: Query<(Entity, &mut Transform, &Projectile), With<Collider>>,
+    bounds: Res<MapBounds>,
 ) {
+    let (min, max) = bounds.world_rect();
     for (projectile_ent, mut projectile_t, projectile) in projectile_q.iter_mut() {
-        if projectile_t.translation.x > MAP_SIZE as f32 * TILE_SIZE
-            || projectile_t.translation.y > MAP_SIZE as f32 * TILE_SIZE
-            || projectile_t.translation.x < -MAP_SIZE as f32 * TILE_SIZE
-            || projectile_t.translation.y < -MAP_SIZE as f32 * TILE_SIZE
-        {
+        let position = projectile_t.translation.truncate();
+        if position.cmpgt(max).any() || position.cmplt(min).any() {
             commands.entity(projectile_ent).despawn();
         }
 
@@ -589,13 +1391,13 @@ pub fn handle_projectiles(
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn handle_projectile_collisions(
     mut commands: Commands,
     mut projectile_q: Query<(&mut Transform, &mut Projectile), With<Collider>>,
     mut enemies: Query<(Entity, &mut Enemy, &Transform), (Without<Projectile>, With<Collider>)>,
+    mut rng: ResMut<GameRng>,
 ) {
-    let mut rng = thread_rng();
-
     for (enemy_ent, mut enemy, enemy_t) in enemies.iter_mut() {
         for (mut projectile_t, mut projectile) in projectile_q.iter_mut() {
             let enemy_scale = enemy_t.scale.truncate() * SPRITE_SIZE; // TODO fix relative scale of enemies
@@ -610,51 +1412,70 @@ pub fn handle_projectile_collisions(
             let enemy_entid = commands.entity(enemy_ent).id();
             if collision.is_some() && !projectile.hit_enemies.contains(&enemy_entid) {
                 projectile.health -= 1;
-                enemy.health -= 1;
+                enemy.health -= projectile.damage;
                 projectile.hit_enemies.push(enemy_entid);
                 projectile_t.rotation *= Quat::from_euler(
                     EulerRot::XYZ,
                     0.,
                     0.,
-                    rng.gen_range(-MAX_DEFLECTION_ANGLE..MAX_DEFLECTION_ANGLE),
+                    rng.gameplay
+                        .gen_range(-MAX_DEFLECTION_ANGLE..MAX_DEFLECTION_ANGLE),
                 );
             }
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn handle_enemies(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    enemy_table: Res<EnemyTableHandle>,
+    enemy_tables: Res<Assets<EnemyTable>>,
     mut enemies: Query<(Entity, &mut Enemy, &Transform)>,
+    mut money: ResMut<Money>,
+    mut rng: ResMut<GameRng>,
 ) {
-    let mut rng = thread_rng();
+    let Some(table) = enemy_tables.get(&enemy_table.0) else {
+        return;
+    };
 
     for (enemy_ent, enemy, enemy_t) in enemies.iter_mut() {
         if enemy.health <= 0 {
             commands.entity(enemy_ent).despawn();
 
-            if let Some((amount, kind)) = enemy.split() {
-                for i in 1..=amount {
-                    let j = i as f32;
-                    commands.spawn((
-                        EnemyBundle::new(
-                            Enemy::new(kind.clone(), enemy.idx),
-                            &asset_server,
-                            &mut texture_atlases,
+            let Some(enemy_type) = table.get(&enemy.kind) else {
+                continue;
+            };
+            money.0 += enemy_type.reward;
+
+            let Some(split) = enemy_type.split.as_ref() else {
+                continue;
+            };
+            let Some(split_type) = table.get(&split.kind) else {
+                continue;
+            };
+
+            let amount = split.count;
+            for i in 1..=amount {
+                let j = i as f32;
+                spawn_enemy(
+                    &mut commands,
+                    &asset_server,
+                    &mut texture_atlases,
+                    Enemy {
+                        next: enemy.next,
+                        ..Enemy::new(split.kind.clone(), split_type, enemy.path, enemy.idx)
+                    },
+                    split_type,
+                    enemy_t.translation
+                        + Vec2::new(
+                            rng.gameplay.gen_range(0. ..TILE_SIZE / amount as f32) * (j - 1.),
+                            rng.gameplay.gen_range(0. ..TILE_SIZE / amount as f32) * (j - 1.),
                         )
-                        .with_position(
-                            enemy_t.translation
-                                + Vec2::new(
-                                    rng.gen_range(0..TILE_SIZE as i32 / amount) as f32 * (j - 1.),
-                                    rng.gen_range(0..TILE_SIZE as i32 / amount) as f32 * (j - 1.),
-                                )
-                                .extend(0.),
-                        ),
-                        Collider(ColliderType::Enemy),
-                    ));
-                }
+                        .extend(0.),
+                );
             }
         }
     }
@@ -662,26 +1483,24 @@ pub fn handle_enemies(
 
 pub fn update_scoreboard(
     menu: Res<Menu>,
+    lives: Res<Lives>,
+    money: Res<Money>,
+    speed: Res<GameSpeed>,
     mut query: Query<&mut Text, With<GUIText>>,
     windows: Res<Windows>,
     camera_q: Query<&Transform, With<Camera>>,
 ) {
     let window = windows.get_primary().unwrap();
-    let window_size = Vec2::new(window.width(), window.height());
     let camera_transform = camera_q.get_single().unwrap();
     if let Ok(mut text) = query.get_single_mut() {
-        if let Some(cursor_position) = window.cursor_position() {
-            let mut cursor_grid =
-                cursor_position - (window_size / 2.) + camera_transform.translation.truncate();
-
-            cursor_grid = (cursor_grid / TILE_SIZE).floor();
-            text.sections[1].value = format!(
-                "{:?} {:>3} {:>3}",
-                menu.current_item, cursor_grid.x, cursor_grid.y
-            );
+        if let Some(GridPos(cell)) = cursor_cell(window, camera_transform) {
+            text.sections[1].value = format!("{:?} {:>3} {:>3}", menu.current_item, cell.x, cell.y);
         } else {
             text.sections[1].value = format!("{:?} --- ---", menu.current_item);
         }
+        text.sections[3].value = lives.0.to_string();
+        text.sections[5].value = money.0.to_string();
+        text.sections[7].value = format!("{}x", speed.0);
     }
 }
 
@@ -4,6 +4,25 @@ use bevy::{
 };
 
 use crate::*;
+
+/// Places a text at a fixed spot of the window
+fn pinned(position: UiRect) -> Style {
+    Style {
+        position_type: PositionType::Absolute,
+        position,
+        ..default()
+    }
+}
+
+/// Where menus and messages go
+fn top_left() -> Style {
+    pinned(UiRect {
+        top: SCOREBOARD_TEXT_PADDING,
+        left: SCOREBOARD_TEXT_PADDING,
+        ..default()
+    })
+}
+
 #[derive(Component)]
 pub struct GUIText;
 
@@ -25,21 +44,56 @@ impl GUIBundle {
                         color: TEXT_COLOR,
                     },
                 ),
+                TextSection::from_style(TextStyle {
+                    font: font.clone(),
+                    font_size: SCOREBOARD_FONT_SIZE,
+                    color: SCORE_COLOR,
+                }),
+                TextSection::new(
+                    "  Lives: ",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: SCOREBOARD_FONT_SIZE,
+                        color: TEXT_COLOR,
+                    },
+                ),
+                TextSection::from_style(TextStyle {
+                    font: font.clone(),
+                    font_size: SCOREBOARD_FONT_SIZE,
+                    color: SCORE_COLOR,
+                }),
+                TextSection::new(
+                    "  Credits: ",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: SCOREBOARD_FONT_SIZE,
+                        color: TEXT_COLOR,
+                    },
+                ),
+                TextSection::from_style(TextStyle {
+                    font: font.clone(),
+                    font_size: SCOREBOARD_FONT_SIZE,
+                    color: SCORE_COLOR,
+                }),
+                TextSection::new(
+                    "  Speed: ",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: SCOREBOARD_FONT_SIZE,
+                        color: TEXT_COLOR,
+                    },
+                ),
                 TextSection::from_style(TextStyle {
                     font,
                     font_size: SCOREBOARD_FONT_SIZE,
                     color: SCORE_COLOR,
                 }),
             ])
-            .with_style(Style {
-                position_type: PositionType::Absolute,
-                position: UiRect {
-                    bottom: SCOREBOARD_TEXT_PADDING,
-                    left: SCOREBOARD_TEXT_PADDING,
-                    ..default()
-                },
+            .with_style(pinned(UiRect {
+                bottom: SCOREBOARD_TEXT_PADDING,
+                left: SCOREBOARD_TEXT_PADDING,
                 ..default()
-            }),
+            })),
             text: GUIText,
         }
     }
@@ -72,16 +126,232 @@ impl FPSBundle {
                     color: SCORE_COLOR,
                 }),
             ])
-            .with_style(Style {
-                position_type: PositionType::Absolute,
-                position: UiRect {
-                    top: SCOREBOARD_TEXT_PADDING,
-                    right: SCOREBOARD_TEXT_PADDING,
-                    ..default()
-                },
+            .with_style(pinned(UiRect {
+                top: SCOREBOARD_TEXT_PADDING,
+                right: SCOREBOARD_TEXT_PADDING,
                 ..default()
-            }),
+            })),
             text: FPSText,
         }
     }
 }
+
+#[derive(Component)]
+pub struct LevelErrorText;
+
+#[derive(Bundle)]
+pub struct LevelErrorBundle {
+    text_bundle: TextBundle,
+    text: LevelErrorText,
+}
+
+impl LevelErrorBundle {
+    pub fn new(font: Handle<Font>, errors: &[LevelError]) -> Self {
+        let style = TextStyle {
+            font,
+            font_size: ERROR_FONT_SIZE,
+            color: TEXT_COLOR,
+        };
+
+        let mut sections = vec![TextSection::new(
+            "Level failed to load:\n",
+            TextStyle {
+                color: ERROR_COLOR,
+                ..style.clone()
+            },
+        )];
+        sections.extend(
+            errors
+                .iter()
+                .map(|error| TextSection::new(format!("- {error}\n"), style.clone())),
+        );
+        sections.push(TextSection::new(
+            "\nBackspace: back to level select",
+            style.clone(),
+        ));
+
+        Self {
+            text_bundle: TextBundle::from_sections(sections).with_style(top_left()),
+            text: LevelErrorText,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct LevelSelectText;
+
+#[derive(Bundle)]
+pub struct LevelSelectBundle {
+    text_bundle: TextBundle,
+    text: LevelSelectText,
+}
+
+impl LevelSelectBundle {
+    pub fn new(font: Handle<Font>, campaign: &Campaign, progress: &CampaignProgress) -> Self {
+        let style = TextStyle {
+            font,
+            font_size: SCOREBOARD_FONT_SIZE,
+            color: TEXT_COLOR,
+        };
+
+        let mut sections = vec![TextSection::new("Select a level:\n", style.clone())];
+        for (idx, level) in campaign.levels.iter().enumerate() {
+            let (color, note) = if progress.completed.contains(&level.name) {
+                (SCORE_COLOR, " (done)".to_string())
+            } else if level.is_unlocked(progress) {
+                (TEXT_COLOR, String::new())
+            } else {
+                (
+                    LOCKED_COLOR,
+                    format!(" (finish {})", level.requires.join(", ")),
+                )
+            };
+
+            sections.push(TextSection::new(
+                format!("{}. {}{note}\n", idx + 1, level.name),
+                TextStyle {
+                    color,
+                    ..style.clone()
+                },
+            ));
+        }
+
+        Self {
+            text_bundle: TextBundle::from_sections(sections).with_style(top_left()),
+            text: LevelSelectText,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct EditorText;
+
+#[derive(Bundle)]
+pub struct EditorBundle {
+    text_bundle: TextBundle,
+    text: EditorText,
+}
+
+impl EditorBundle {
+    pub fn new(font: Handle<Font>) -> Self {
+        let style = TextStyle {
+            font,
+            font_size: ERROR_FONT_SIZE,
+            color: TEXT_COLOR,
+        };
+
+        Self {
+            text_bundle: TextBundle::from_sections([
+                TextSection::new("Path 1: 0 tiles\n", style.clone()),
+                TextSection::new(
+                    "\n",
+                    TextStyle {
+                        color: SCORE_COLOR,
+                        ..style.clone()
+                    },
+                ),
+                TextSection::new(
+                    "Tab: path/decor  1-9: path  Q/E: texture  B: blocking\n\
+                     Left click: place  Right click: remove  C: camera center\n\
+                     Arrows: pan  Home: recenter  Ctrl+S: save  F2: play",
+                    style,
+                ),
+            ])
+            .with_style(top_left()),
+            text: EditorText,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct GameOverText;
+
+#[derive(Bundle)]
+pub struct GameOverBundle {
+    text_bundle: TextBundle,
+    text: GameOverText,
+}
+
+impl GameOverBundle {
+    pub fn new(font: Handle<Font>) -> Self {
+        let style = TextStyle {
+            font,
+            font_size: SCOREBOARD_FONT_SIZE,
+            color: TEXT_COLOR,
+        };
+
+        Self {
+            text_bundle: TextBundle::from_sections([
+                TextSection::new(
+                    "Game over\n",
+                    TextStyle {
+                        color: ERROR_COLOR,
+                        ..style.clone()
+                    },
+                ),
+                TextSection::new("R: restart\nBackspace: back to level select", style),
+            ])
+            .with_style(top_left()),
+            text: GameOverText,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct PauseText;
+
+#[derive(Bundle)]
+pub struct PauseBundle {
+    text_bundle: TextBundle,
+    text: PauseText,
+}
+
+impl PauseBundle {
+    pub fn new(font: Handle<Font>) -> Self {
+        let style = TextStyle {
+            font,
+            font_size: SCOREBOARD_FONT_SIZE,
+            color: TEXT_COLOR,
+        };
+
+        Self {
+            text_bundle: TextBundle::from_sections([
+                TextSection::new("Paused\n", style.clone()),
+                TextSection::new("P: resume", style),
+            ])
+            .with_style(top_left()),
+            text: PauseText,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct InspectText;
+
+/// Stats of the hovered tower, below the fps counter
+#[derive(Bundle)]
+pub struct InspectBundle {
+    text_bundle: TextBundle,
+    text: InspectText,
+}
+
+impl InspectBundle {
+    pub fn new(font: Handle<Font>) -> Self {
+        Self {
+            text_bundle: TextBundle::from_section(
+                "",
+                TextStyle {
+                    font,
+                    font_size: ERROR_FONT_SIZE,
+                    color: TEXT_COLOR,
+                },
+            )
+            .with_style(pinned(UiRect {
+                top: Val::Px(SCOREBOARD_FONT_SIZE + 10.),
+                right: SCOREBOARD_TEXT_PADDING,
+                ..default()
+            })),
+            text: InspectText,
+        }
+    }
+}